@@ -1,55 +1,171 @@
 use crate::error::*;
+use crate::feed::BlockFeed;
+use crate::metrics::Metrics;
 use crate::traits::*;
 use crate::types::StreamerResult;
+use crate::webhooks::Webhooks;
+use std::sync::Arc;
 use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
 /// TODO should use a multi-producer-multi-consumer channel
 /// Each block is fetched and parsed by a Consumer task and sent to another multi-producer-multi-consumer channel
-/// Each Aggregator task will take the block from the channel and store 
+/// Each Aggregator task will take the block from the channel and store
 /// This will relieve the need for `Aggregator::run` task to block on self.storage.add_block() operation
 pub struct Aggregator<T: BlockStream, S: Storage> {
     pub streamer: T,
     pub token: CancellationToken,
     pub storage: S,
+    /// Stored blocks are published here for live subscribers
+    pub feed: BlockFeed,
+    pub metrics: Metrics,
+    /// Notified of stored blocks, sending webhooks for watched addresses
+    pub webhooks: Webhooks,
 }
 
 impl<T: BlockStream, S: Storage> Aggregator<T, S> {
-    pub fn new(streamer: T, token: CancellationToken, storage: S) -> Self {
-        log::debug!("Created successfully");
+    pub fn new(
+        streamer: T,
+        token: CancellationToken,
+        storage: S,
+        feed: BlockFeed,
+        metrics: Metrics,
+        webhooks: Webhooks,
+    ) -> Self {
+        tracing::debug!("Created successfully");
         Self {
             streamer,
             token,
             storage,
+            feed,
+            metrics,
+            webhooks,
         }
     }
 
+    /// Records streamed blocks until cancelled
+    /// Once cancelled, the slots still queued are drained and storage is flushed
     pub async fn run(&mut self) -> Result<()> {
-        loop {
-            if self.token.is_cancelled() {
-                log::info!("TERMINATING");
-                return Err(Error::Termination);
-            }
+        let result = self.record().await;
+        if self.token.is_cancelled() {
+            tracing::info!("Flushing storage");
+            self.storage.flush().await?;
+            tracing::info!("TERMINATING");
+        }
+        result
+    }
 
+    async fn record(&mut self) -> Result<()> {
+        loop {
             match self.streamer.next().await {
-                StreamerResult::Block(block) => {
-                    log::info!("Recording block: {:?}", block.height);
-                    self.storage.add_block(&block).await?;
+                StreamerResult::Block(block, span) => {
+                    // e.g. when backfilling over stored slots
+                    if self.storage.get_block(block.height).await.is_ok() {
+                        span.in_scope(|| tracing::debug!("Block already stored"));
+                        continue;
+                    }
+                    let store = tracing::info_span!(parent: &span, "store");
+                    store.in_scope(|| tracing::info!("Recording block"));
+                    self.storage.add_block(&block).instrument(store).await?;
+                    self.metrics.blocks_ingested.inc();
+                    self.metrics
+                        .transfers_parsed
+                        .inc_by(block.transactions.len() as u64);
+                    // blocks backfilled behind the tip don't move it back
+                    if block.slot as i64 > self.metrics.last_stored_slot.get() {
+                        self.metrics.last_stored_slot.set(block.slot as i64);
+                    }
+                    span.in_scope(|| self.webhooks.notify(&block));
+                    // fails only when nobody is subscribed
+                    let _ = self.feed.send(Arc::new(block));
                 }
                 StreamerResult::Error(error) => {
-                    // check if a slot was missing or skipped
-                    log::warn!("{}", error);
-                    if let Error::SlotSkipped(_) | Error::SlotMissing(_) = error {
-                        continue;
+                    // check if a slot was missing or skipped, it's span already recorded the error
+                    tracing::debug!("{}", error);
+                    match error {
+                        Error::SlotSkipped(_) => self.metrics.slots_skipped.inc(),
+                        Error::SlotMissing(_) => self.metrics.slots_missing.inc(),
+                        _ => return Err(error),
                     }
-                    return Err(error);
+                }
+                // the queue is drained
+                StreamerResult::EOS() if self.token.is_cancelled() => {
+                    return Err(Error::Termination);
                 }
                 // EOS is not an error, just log and continue
                 // Alternatively, we could `sleep` for a while before continuing
                 // Not sure how to decide on sleep duration
                 StreamerResult::EOS() => {
-                    log::debug!("EOS");
+                    tracing::debug!("EOS");
                 }
             }
         }
     }
 }
+
+/// Streams queued blocks then EOS, standing in for the streamer in tests
+/// Blocks come with their slot span, filled in as the streamer does
+#[cfg(test)]
+pub(crate) struct QueuedBlocks(pub std::collections::VecDeque<crate::types::Block>);
+
+#[cfg(test)]
+impl BlockStream for QueuedBlocks {
+    async fn next(&mut self) -> StreamerResult {
+        match self.0.pop_front() {
+            Some(block) => {
+                let span = crate::telemetry::slot_span(block.slot);
+                span.record("height", block.height);
+                span.record("transfers", block.transactions.len());
+                StreamerResult::Block(block, span)
+            }
+            None => StreamerResult::EOS(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod aggregator_tests {
+    use crate::aggregator::*;
+    use crate::feed;
+    use crate::storage::InMemoryStorage;
+    use crate::types::Block;
+    use std::collections::VecDeque;
+
+    #[tokio::test]
+    async fn drain() {
+        let mut blocks: VecDeque<Block> = (1..=3)
+            .map(|height| Block {
+                height,
+                slot: height + 100,
+                hash: format!("block_hash{}", height),
+                timestamp: height as i64,
+                transactions: Vec::new(),
+            })
+            .collect();
+        // blocks already stored are skipped
+        blocks.push_back(blocks[1].clone());
+        // as are older blocks when tracking the last stored slot
+        let mut older = blocks[0].clone();
+        older.height = 0;
+        older.slot = 100;
+        blocks.push_back(older);
+        let token = CancellationToken::new();
+        let metrics = Metrics::new();
+        let mut aggregator = Aggregator::new(
+            QueuedBlocks(blocks),
+            token.clone(),
+            InMemoryStorage::new(),
+            feed::block_feed(),
+            metrics.clone(),
+            Webhooks::disabled(),
+        );
+
+        // blocks queued before cancellation are still recorded
+        token.cancel();
+        assert!(matches!(aggregator.run().await, Err(Error::Termination)));
+        let latest = aggregator.storage.get_latest_block().await.unwrap();
+        assert_eq!(latest.height, 3);
+        assert_eq!(metrics.blocks_ingested.get(), 4);
+        assert_eq!(metrics.last_stored_slot.get(), 103);
+    }
+}
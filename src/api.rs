@@ -1,108 +1,876 @@
-use crate::storage::Database;
+use crate::auth::{self, ApiKeys};
+use crate::error::Error;
+use crate::feed::{self, BlockFeed};
+use crate::graphql;
+use crate::health::{self, Health};
+use crate::metrics;
+use crate::openapi;
+use crate::ratelimit::{self, RateLimited, RateLimiter};
+use crate::supervisor;
 use crate::traits::Storage;
-use crate::types::Address;
-use serde::Deserialize;
+use crate::types::{
+    Account, Address, At, BalanceBucket, BlockInfo, BlockQuery, BlockSummary, Cursor, Interval,
+    Order, SeriesQuery, Transaction, TransactionPage, TransactionQuery,
+};
+use crate::webhooks::{self, Webhooks};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use solana_program::clock::Slot;
+use std::collections::HashSet;
+use std::convert::Infallible;
 use std::net::SocketAddr;
 use tokio_util::sync::CancellationToken;
-use warp;
-use warp::Filter;
+use utoipa::{IntoParams, ToSchema};
+use warp::http::header::{HeaderValue, RETRY_AFTER};
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
 
-#[derive(Deserialize)]
+/// Page size used when the client doesn't ask for one
+pub const DEFAULT_PAGE_LIMIT: usize = 100;
+/// Upper bound on the page size a client can ask for
+pub const MAX_PAGE_LIMIT: usize = 1000;
+/// Most addresses a batch request can look up at once
+pub const MAX_BATCH_ADDRESSES: usize = 1000;
+/// Most transactions a batch request can return, the page size times the number of addresses
+pub const MAX_BATCH_TRANSACTIONS: usize = 10_000;
+/// Largest request body accepted, in bytes
+pub const MAX_BODY_BYTES: u64 = 128 * 1024;
+
+/// Errors surfaced to API clients, each maps to a HTTP status code
+#[derive(Debug)]
+pub enum ApiError {
+    NotFound(String),
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    /// The request conflicts with how the resource is configured
+    Conflict(String),
+    /// Seconds until the client can retry are sent in `Retry-After`
+    TooManyRequests(String, u64),
+    Unavailable(String),
+    Internal(String),
+}
+
+impl warp::reject::Reject for ApiError {}
+
+impl From<Error> for ApiError {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::NotFound(_) => Self::NotFound(error.to_string()),
+            Error::InvalidInput(_) => Self::BadRequest(error.to_string()),
+            Error::StorageError(_) => Self::Unavailable(error.to_string()),
+            Error::ReadOnly => Self::Forbidden(error.to_string()),
+            _ => Self::Internal(error.to_string()),
+        }
+    }
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::NotFound(message)
+            | Self::BadRequest(message)
+            | Self::Unauthorized(message)
+            | Self::Forbidden(message)
+            | Self::Conflict(message)
+            | Self::TooManyRequests(message, _)
+            | Self::Unavailable(message)
+            | Self::Internal(message) => message,
+        }
+    }
+}
+
+/// Body of every error response
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ErrorBody {
+    /// HTTP status code
+    pub code: u16,
+    pub message: String,
+}
+
+/// Turns rejections into a status code and an [ErrorBody]
+pub async fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Infallible> {
+    let retry_after = match rejection.find::<ApiError>() {
+        Some(ApiError::TooManyRequests(_, retry_after)) => Some(*retry_after),
+        _ => None,
+    };
+    let (status, message) = if let Some(error) = rejection.find::<ApiError>() {
+        (error.status(), error.message().to_string())
+    } else if let Some(limited) = rejection.find::<RateLimited>() {
+        let message = format!("Rate limit exceeded, retry in {} seconds", limited.0.reset);
+        (StatusCode::TOO_MANY_REQUESTS, message)
+    } else if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, String::from("Route not found"))
+    } else if let Some(error) = rejection.find::<warp::reject::InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, error.to_string())
+    } else if let Some(error) = rejection.find::<warp::reject::InvalidHeader>() {
+        (StatusCode::BAD_REQUEST, error.to_string())
+    } else if let Some(error) = rejection.find::<warp::reject::MissingHeader>() {
+        (StatusCode::BAD_REQUEST, error.to_string())
+    } else if let Some(error) = rejection.find::<warp::ws::MissingConnectionUpgrade>() {
+        (StatusCode::BAD_REQUEST, error.to_string())
+    } else if let Some(error) = rejection.find::<warp::filters::body::BodyDeserializeError>() {
+        (StatusCode::BAD_REQUEST, error.to_string())
+    } else if let Some(error) = rejection.find::<async_graphql_warp::GraphQLBadRequest>() {
+        (StatusCode::BAD_REQUEST, error.to_string())
+    } else if rejection.find::<warp::reject::PayloadTooLarge>().is_some() {
+        let message = format!("Request body is over {} bytes", MAX_BODY_BYTES);
+        (StatusCode::PAYLOAD_TOO_LARGE, message)
+    } else if rejection.find::<warp::reject::LengthRequired>().is_some() {
+        (
+            StatusCode::LENGTH_REQUIRED,
+            String::from("Content-Length required"),
+        )
+    } else if rejection
+        .find::<warp::reject::UnsupportedMediaType>()
+        .is_some()
+    {
+        (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            String::from("Expected a JSON body"),
+        )
+    } else if rejection.find::<warp::reject::MethodNotAllowed>().is_some() {
+        (
+            StatusCode::METHOD_NOT_ALLOWED,
+            String::from("Method not allowed"),
+        )
+    } else {
+        tracing::error!("Unhandled rejection: {:?}", rejection);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            String::from("Internal server error"),
+        )
+    };
+
+    let body = ErrorBody {
+        code: status.as_u16(),
+        message,
+    };
+    let mut response = warp::reply::with_status(warp::reply::json(&body), status).into_response();
+    if let Some(retry_after) = retry_after {
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    }
+    if let Some(limited) = rejection.find::<RateLimited>() {
+        limited.apply(&mut response);
+    }
+    Ok(response)
+}
+
+/// Like [warp::query], but rejects with the reason the query string failed to parse
+/// e.g. which parameter is missing or which address is malformed
+fn query<T>() -> impl Filter<Extract = (T,), Error = Rejection> + Clone
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .and_then(|query: String| async move {
+            serde_urlencoded::from_str::<T>(&query)
+                .map_err(|error| warp::reject::custom(ApiError::BadRequest(error.to_string())))
+        })
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ApiParam {
     pub address: Address,
+    /// Looks the account up as of `slot:<slot>` or `time:<unix seconds>`
+    pub at: Option<At>,
 }
 
-/// Gets all transactions associated witn an account
-async fn get_transactions(
-    params: ApiParam,
-    storage_interface: Database,
-) -> Result<impl warp::Reply, warp::Rejection> {
-    log::debug!("Get transactions for address: {:?}", params.address);
-    match storage_interface.get_transactions(&params.address).await {
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TransactionsParam {
+    pub address: Address,
+    /// Page size, defaults to 100 and is capped at 1000
+    pub limit: Option<usize>,
+    /// `next_cursor` of the previous page
+    pub cursor: Option<Cursor>,
+    #[serde(default)]
+    pub order: Order,
+    /// Inclusive slot bounds
+    pub from_slot: Option<Slot>,
+    pub to_slot: Option<Slot>,
+    /// Inclusive block time bounds, unix seconds
+    pub from_time: Option<i64>,
+    pub to_time: Option<i64>,
+}
+
+impl TransactionsParam {
+    pub fn query(&self) -> TransactionQuery {
+        TransactionQuery {
+            limit: self
+                .limit
+                .unwrap_or(DEFAULT_PAGE_LIMIT)
+                .clamp(1, MAX_PAGE_LIMIT),
+            cursor: self.cursor,
+            order: self.order,
+            from_slot: self.from_slot,
+            to_slot: self.to_slot,
+            from_time: self.from_time,
+            to_time: self.to_time,
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryParam {
+    pub address: Address,
+    #[serde(default)]
+    pub interval: Interval,
+    /// Inclusive time bounds, unix seconds
+    pub from_time: Option<i64>,
+    pub to_time: Option<i64>,
+}
+
+impl HistoryParam {
+    pub fn query(&self) -> SeriesQuery {
+        SeriesQuery {
+            interval: self.interval,
+            from_time: self.from_time,
+            to_time: self.to_time,
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BlocksParam {
+    /// Inclusive height bounds
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    /// Page size, defaults to 100 and is capped at 1000
+    pub limit: Option<usize>,
+}
+
+impl BlocksParam {
+    pub fn query(&self) -> BlockQuery {
+        BlockQuery {
+            from: self.from,
+            to: self.to,
+            limit: self
+                .limit
+                .unwrap_or(DEFAULT_PAGE_LIMIT)
+                .clamp(1, MAX_PAGE_LIMIT),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct BlocksResponse {
+    pub blocks: Vec<BlockInfo>,
+    /// `from` of the following page, `None` if this is the last one
+    pub next_height: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AccountsRequest {
+    pub addresses: Vec<Address>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AccountsResponse {
+    /// Stored accounts, in the order they were requested
+    pub accounts: Vec<Account>,
+    /// Requested addresses without a stored account
+    pub not_found: Vec<Address>,
+}
+
+/// Same filters as `/transactions`, applied to the history of each address
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct TransactionsBatchRequest {
+    pub addresses: Vec<Address>,
+    /// Page size per address, defaults to 100 and is capped at 1000,
+    /// times the number of distinct addresses it can't exceed 10000
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub order: Order,
+    pub from_slot: Option<Slot>,
+    pub to_slot: Option<Slot>,
+    pub from_time: Option<i64>,
+    pub to_time: Option<i64>,
+}
+
+impl TransactionsBatchRequest {
+    pub fn query(&self) -> TransactionQuery {
+        TransactionQuery {
+            limit: self
+                .limit
+                .unwrap_or(DEFAULT_PAGE_LIMIT)
+                .clamp(1, MAX_PAGE_LIMIT),
+            cursor: None,
+            order: self.order,
+            from_slot: self.from_slot,
+            to_slot: self.to_slot,
+            from_time: self.from_time,
+            to_time: self.to_time,
+        }
+    }
+}
+
+/// First page of an address's transactions in a batch
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AddressTransactions {
+    pub address: Address,
+    pub transactions: Vec<Transaction>,
+    /// Continues with `/transactions?address=<address>&cursor=<next_cursor>`
+    pub next_cursor: Option<Cursor>,
+}
+
+/// Addresses of a batch request without duplicates, in the order they were first given
+fn batch_addresses(addresses: &[Address]) -> Result<Vec<Address>, Rejection> {
+    if addresses.len() > MAX_BATCH_ADDRESSES {
+        return Err(warp::reject::custom(ApiError::BadRequest(format!(
+            "Too many addresses: {}, at most {} can be looked up at once",
+            addresses.len(),
+            MAX_BATCH_ADDRESSES
+        ))));
+    }
+    let mut seen = HashSet::new();
+    Ok(addresses
+        .iter()
+        .filter(|address| seen.insert(**address))
+        .copied()
+        .collect())
+}
+
+/// Gets a page of transactions associated witn an account
+#[utoipa::path(
+    get,
+    path = "/transactions",
+    tag = "accounts",
+    params(TransactionsParam),
+    responses(
+        (status = 200, description = "Page of transactions", body = TransactionPage),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+        (status = 404, description = "No transactions stored for the address", body = ErrorBody),
+    )
+)]
+async fn get_transactions<S: Storage>(
+    params: TransactionsParam,
+    storage_interface: S,
+) -> Result<impl Reply, Rejection> {
+    tracing::debug!("Get transactions for address: {:?}", params.address);
+    match storage_interface
+        .get_transactions(&params.address, &params.query())
+        .await
+    {
         Ok(transactions) => Ok(warp::reply::json(&transactions)),
-        Err(error) => Ok(warp::reply::json(&error.to_string())),
+        Err(error) => Err(warp::reject::custom(ApiError::from(error))),
     }
 }
 
-/// Gets all info stored in an account
+/// Gets all info stored in an account, at a point in history if one is given
 /// For now, returns account balance only.
-async fn get_account(
+#[utoipa::path(
+    get,
+    path = "/account",
+    tag = "accounts",
+    params(ApiParam),
+    responses(
+        (status = 200, description = "Account", body = Account),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+        (status = 404, description = "Account not stored", body = ErrorBody),
+    )
+)]
+async fn get_account<S: Storage>(
     params: ApiParam,
-    storage_interface: Database,
-) -> Result<impl warp::Reply, warp::Rejection> {
-    log::debug!("Get account for address: {:?}", params.address);
-    match storage_interface.get_account(&params.address).await {
+    storage_interface: S,
+) -> Result<impl Reply, Rejection> {
+    tracing::debug!("Get account for address: {:?}", params.address);
+    let account = match params.at {
+        Some(at) => storage_interface.get_account_at(&params.address, at).await,
+        None => storage_interface.get_account(&params.address).await,
+    };
+    match account {
         Ok(accounts) => Ok(warp::reply::json(&accounts)),
-        Err(error) => Ok(warp::reply::json(&error.to_string())),
+        Err(error) => Err(warp::reject::custom(ApiError::from(error))),
     }
 }
 
-/// Starts the API server on the provided socket address
-/// The server will run until the token is cancelled
-/// The server will provide two routes:
-/// - /transactions?address=<address> - returns all transactions associated with the address
-/// - /account?address=<address> - returns all info stored in the account
-pub async fn run_api(address: SocketAddr, db: Database, token: CancellationToken) {
+/// Gets an account's balance and flows over time, bucketed hourly or daily
+#[utoipa::path(
+    get,
+    path = "/account/history",
+    tag = "accounts",
+    params(HistoryParam),
+    responses(
+        (status = 200, description = "Balance series", body = Vec<BalanceBucket>),
+        (status = 400, description = "Invalid query or too many buckets", body = ErrorBody),
+        (status = 404, description = "Account not stored", body = ErrorBody),
+    )
+)]
+async fn get_account_history<S: Storage>(
+    params: HistoryParam,
+    storage_interface: S,
+) -> Result<impl Reply, Rejection> {
+    tracing::debug!("Get account history for address: {:?}", params.address);
+    match storage_interface
+        .get_balance_series(&params.address, &params.query())
+        .await
+    {
+        Ok(series) => Ok(warp::reply::json(&series)),
+        Err(error) => Err(warp::reject::custom(ApiError::from(error))),
+    }
+}
+
+/// Gets many accounts at once
+#[utoipa::path(
+    post,
+    path = "/accounts",
+    tag = "accounts",
+    request_body = AccountsRequest,
+    responses(
+        (status = 200, description = "Accounts found", body = AccountsResponse),
+        (status = 400, description = "Invalid body or too many addresses", body = ErrorBody),
+    )
+)]
+async fn get_accounts<S: Storage>(
+    request: AccountsRequest,
+    storage_interface: S,
+) -> Result<impl Reply, Rejection> {
+    let addresses = batch_addresses(&request.addresses)?;
+    tracing::debug!("Get accounts for {} addresses", addresses.len());
+    let accounts = match storage_interface.get_accounts(&addresses).await {
+        Ok(accounts) => accounts,
+        Err(error) => return Err(warp::reject::custom(ApiError::from(error))),
+    };
+
+    let mut response = AccountsResponse {
+        accounts: Vec::new(),
+        not_found: Vec::new(),
+    };
+    for (address, account) in addresses.into_iter().zip(accounts) {
+        match account {
+            Some(account) => response.accounts.push(account),
+            None => response.not_found.push(address),
+        }
+    }
+    Ok(warp::reply::json(&response))
+}
+
+/// Gets the first page of transactions of many accounts at once
+#[utoipa::path(
+    post,
+    path = "/transactions/batch",
+    tag = "accounts",
+    request_body = TransactionsBatchRequest,
+    responses(
+        (status = 200, description = "Page of transactions per address, in the order requested", body = Vec<AddressTransactions>),
+        (status = 400, description = "Invalid body, too many addresses or transactions", body = ErrorBody),
+    )
+)]
+async fn get_transactions_batch<S: Storage>(
+    request: TransactionsBatchRequest,
+    storage_interface: S,
+) -> Result<impl Reply, Rejection> {
+    let addresses = batch_addresses(&request.addresses)?;
+    let query = request.query();
+    if addresses.len() * query.limit > MAX_BATCH_TRANSACTIONS {
+        return Err(warp::reject::custom(ApiError::BadRequest(format!(
+            "Too many transactions: {} addresses with a limit of {}, at most {} can be returned at once",
+            addresses.len(),
+            query.limit,
+            MAX_BATCH_TRANSACTIONS
+        ))));
+    }
+    tracing::debug!("Get transactions for {} addresses", addresses.len());
+    let pages = match storage_interface
+        .get_transactions_batch(&addresses, &query)
+        .await
+    {
+        Ok(pages) => pages,
+        Err(error) => return Err(warp::reject::custom(ApiError::from(error))),
+    };
+
+    let response: Vec<AddressTransactions> = addresses
+        .into_iter()
+        .zip(pages)
+        .map(|(address, page)| AddressTransactions {
+            address,
+            transactions: page.transactions,
+            next_cursor: page.next_cursor,
+        })
+        .collect();
+    Ok(warp::reply::json(&response))
+}
+
+/// Gets a stored block by height
+#[utoipa::path(
+    get,
+    path = "/blocks/{height}",
+    tag = "blocks",
+    params(("height" = u64, Path, description = "Block height")),
+    responses(
+        (status = 200, description = "Block", body = BlockInfo),
+        (status = 404, description = "Block not stored", body = ErrorBody),
+    )
+)]
+async fn get_block<S: Storage>(height: u64, storage_interface: S) -> Result<impl Reply, Rejection> {
+    tracing::debug!("Get block: {:?}", height);
+    match storage_interface.get_block(height).await {
+        Ok(block) => Ok(warp::reply::json(&BlockInfo::from(block))),
+        Err(error) => Err(warp::reject::custom(ApiError::from(error))),
+    }
+}
+
+/// Gets the highest stored block
+#[utoipa::path(
+    get,
+    path = "/blocks/latest",
+    tag = "blocks",
+    responses(
+        (status = 200, description = "Block", body = BlockInfo),
+        (status = 404, description = "No blocks stored", body = ErrorBody),
+    )
+)]
+async fn get_latest_block<S: Storage>(storage_interface: S) -> Result<impl Reply, Rejection> {
+    tracing::debug!("Get latest block");
+    match storage_interface.get_latest_block().await {
+        Ok(block) => Ok(warp::reply::json(&BlockInfo::from(block))),
+        Err(error) => Err(warp::reject::custom(ApiError::from(error))),
+    }
+}
+
+/// Gets a page of stored blocks in ascending height
+#[utoipa::path(
+    get,
+    path = "/blocks",
+    tag = "blocks",
+    params(BlocksParam),
+    responses(
+        (status = 200, description = "Page of blocks", body = BlocksResponse),
+        (status = 400, description = "Invalid query", body = ErrorBody),
+    )
+)]
+async fn get_blocks<S: Storage>(
+    params: BlocksParam,
+    storage_interface: S,
+) -> Result<impl Reply, Rejection> {
+    tracing::debug!("Get blocks from: {:?} to: {:?}", params.from, params.to);
+    match storage_interface.get_blocks(&params.query()).await {
+        Ok(page) => Ok(warp::reply::json(&BlocksResponse {
+            blocks: page.blocks.into_iter().map(BlockInfo::from).collect(),
+            next_height: page.next_height,
+        })),
+        Err(error) => Err(warp::reject::custom(ApiError::from(error))),
+    }
+}
+
+/// Streams transactions matching a [feed::Subscription] sent by the client
+#[utoipa::path(
+    get,
+    path = "/stream/transactions",
+    tag = "streams",
+    responses(
+        (status = 101, description = "WebSocket, send a `Subscription` to receive matching `Transaction`s"),
+        (status = 400, description = "Not a WebSocket upgrade request", body = ErrorBody),
+    )
+)]
+fn stream_transactions(ws: warp::ws::Ws, feed: BlockFeed) -> impl Reply {
+    let blocks = feed.subscribe();
+    ws.on_upgrade(move |socket| feed::serve_transactions(socket, blocks))
+}
+
+/// Streams a summary of each newly stored block, replaying those after `Last-Event-ID`
+#[utoipa::path(
+    get,
+    path = "/stream/blocks",
+    tag = "streams",
+    params(("Last-Event-ID" = Option<u64>, Header, description = "Height of the last block received")),
+    responses(
+        (status = 200, description = "Server-Sent Events named `block`", content_type = "text/event-stream", body = BlockSummary),
+        (status = 400, description = "`Last-Event-ID` isn't a block height", body = ErrorBody),
+    )
+)]
+fn stream_blocks<S>(last_event_id: Option<u64>, storage_interface: S, feed: BlockFeed) -> impl Reply
+where
+    S: Storage + Sync + 'static,
+{
+    let events = feed::block_events(storage_interface, feed.subscribe(), last_event_id);
+    warp::sse::reply(warp::sse::keep_alive().stream(events))
+}
+
+/// All routes served by the API, rejections are recovered into error responses
+/// Unless `keys` is [ApiKeys::open], all but the docs require an API key, see [auth::authorize]
+/// The same routes are rate limited per client by `limiter`, before reaching storage
+/// - /transactions?address=<address>&limit=<limit>&cursor=<cursor>&order=<asc|desc>
+///   &from_slot=<slot>&to_slot=<slot>&from_time=<unix>&to_time=<unix>
+///   returns a page of transactions associated with the address
+/// - /account?address=<address>&at=<slot:<slot>|time:<unix>>
+///   returns all info stored in the account, as of `at` if given
+/// - POST /accounts - returns the accounts of the addresses in the body, see [AccountsRequest]
+/// - POST /transactions/batch - returns a page of transactions for each address in the body
+/// - /account/history?address=<address>&interval=<hour|day>&from_time=<unix>&to_time=<unix>
+///   returns the account's balance series
+/// - /blocks/<height> - returns the block at height
+/// - /blocks/latest - returns the highest stored block
+/// - /blocks?from=<height>&to=<height>&limit=<limit> - returns a page of blocks
+/// - /stream/transactions - WebSocket streaming newly stored transactions matching a subscription
+/// - /stream/blocks - Server-Sent Events of newly stored blocks, resumable with `Last-Event-ID`
+/// - /graphql, /graphiql - see [graphql::routes]
+/// - /openapi.json, /docs - see [openapi::routes]
+/// - /metrics - see [metrics::routes]
+/// - /healthz, /readyz - see [health::routes]
+/// - /status - see [supervisor::routes]
+/// - /admin/keys - see [auth::routes]
+/// - /admin/watchlist, /admin/webhooks - see [webhooks::routes]
+pub fn routes<S>(
+    db: S,
+    feed: BlockFeed,
+    keys: ApiKeys,
+    limiter: RateLimiter,
+    health: Health,
+    webhooks: Webhooks,
+) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
     let db_move = db.clone();
     let get_transactions_route = warp::path!("transactions")
-        .and(warp::query::<ApiParam>())
+        .and(query::<TransactionsParam>())
         .and(warp::any().map(move || db_move.clone()))
         .and_then(get_transactions);
 
     let db_move = db.clone();
     let get_accounts_route = warp::path!("account")
-        .and(warp::query::<ApiParam>())
+        .and(query::<ApiParam>())
         .and(warp::any().map(move || db_move.clone()))
         .and_then(get_account);
 
-    let routes = get_accounts_route.or(get_transactions_route);
+    let db_move = db.clone();
+    let get_accounts_batch_route = warp::path!("accounts")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and(warp::any().map(move || db_move.clone()))
+        .and_then(get_accounts);
+
+    let db_move = db.clone();
+    let get_transactions_batch_route = warp::path!("transactions" / "batch")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_BODY_BYTES))
+        .and(warp::body::json())
+        .and(warp::any().map(move || db_move.clone()))
+        .and_then(get_transactions_batch);
+
+    let db_move = db.clone();
+    let get_account_history_route = warp::path!("account" / "history")
+        .and(query::<HistoryParam>())
+        .and(warp::any().map(move || db_move.clone()))
+        .and_then(get_account_history);
+
+    let feed_move = feed.clone();
+    let stream_transactions_route = warp::path!("stream" / "transactions")
+        .and(warp::ws())
+        .and(warp::any().map(move || feed_move.clone()))
+        .map(stream_transactions);
+
+    let db_move = db.clone();
+    let stream_blocks_route = warp::path!("stream" / "blocks")
+        .and(warp::get())
+        .and(warp::header::optional::<u64>("last-event-id"))
+        .and(warp::any().map(move || db_move.clone()))
+        .and(warp::any().map(move || feed.clone()))
+        .map(stream_blocks);
+
+    let db_move = db.clone();
+    let get_latest_block_route = warp::path!("blocks" / "latest")
+        .and(warp::any().map(move || db_move.clone()))
+        .and_then(get_latest_block);
+
+    let db_move = db.clone();
+    let get_block_route = warp::path!("blocks" / u64)
+        .and(warp::any().map(move || db_move.clone()))
+        .and_then(get_block);
+
+    let db_move = db.clone();
+    let get_blocks_route = warp::path!("blocks")
+        .and(query::<BlocksParam>())
+        .and(warp::any().map(move || db_move.clone()))
+        .and_then(get_blocks);
+
+    let admin_routes = auth::routes(db.clone(), keys.clone()).or(webhooks::routes(
+        db.clone(),
+        keys.clone(),
+        webhooks,
+    ));
+    let db_health = db.clone();
+
+    let api_routes = get_accounts_route
+        .or(get_accounts_batch_route)
+        .or(get_account_history_route)
+        .or(get_transactions_route)
+        .or(get_transactions_batch_route)
+        .or(get_latest_block_route)
+        .or(get_block_route)
+        .or(get_blocks_route)
+        .or(stream_transactions_route)
+        .or(stream_blocks_route)
+        .or(supervisor::routes(health.clone()))
+        .or(graphql::routes(db));
+
+    // recovered within the limit, so error responses carry the client's standing as well
+    let limited_routes = admin_routes
+        .or(auth::authorize(keys.clone()).and(api_routes))
+        .recover(handle_rejection);
+
+    let metrics = health.metrics().clone();
+    openapi::routes()
+        .or(metrics::routes(metrics.clone()))
+        .or(health::routes(db_health, health))
+        .or(ratelimit::limit(limiter, keys)
+            .and(limited_routes)
+            .map(ratelimit::with_headers))
+        .recover(handle_rejection)
+        .with(metrics::track(metrics))
+}
+
+/// [routes] for tests, parts that aren't set are empty, open or disabled
+#[cfg(test)]
+pub(crate) struct TestRoutes<S = crate::storage::InMemoryStorage> {
+    storage: S,
+    feed: BlockFeed,
+    keys: ApiKeys,
+    limiter: RateLimiter,
+    health: Health,
+    webhooks: Webhooks,
+}
+
+#[cfg(test)]
+impl Default for TestRoutes {
+    fn default() -> Self {
+        Self::with_storage(crate::storage::InMemoryStorage::new())
+    }
+}
+
+#[cfg(test)]
+impl<S> TestRoutes<S>
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    pub fn with_storage(storage: S) -> Self {
+        Self {
+            storage,
+            feed: feed::block_feed(),
+            keys: ApiKeys::open(),
+            limiter: RateLimiter::disabled(),
+            health: Health::default(),
+            webhooks: Webhooks::disabled(),
+        }
+    }
+
+    pub fn feed(self, feed: BlockFeed) -> Self {
+        Self { feed, ..self }
+    }
+
+    pub fn keys(self, keys: ApiKeys) -> Self {
+        Self { keys, ..self }
+    }
+
+    pub fn limiter(self, limiter: RateLimiter) -> Self {
+        Self { limiter, ..self }
+    }
+
+    pub fn health(self, health: Health) -> Self {
+        Self { health, ..self }
+    }
+
+    pub fn webhooks(self, webhooks: Webhooks) -> Self {
+        Self { webhooks, ..self }
+    }
+
+    pub fn build(self) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+        routes(
+            self.storage,
+            self.feed,
+            self.keys,
+            self.limiter,
+            self.health,
+            self.webhooks,
+        )
+    }
+}
+
+/// Starts the API server on the provided socket address
+/// The server will run until the token is cancelled
+/// Serves `routes`, as built by [routes]
+pub async fn run_api<F>(address: SocketAddr, routes: F, token: CancellationToken)
+where
+    F: Filter<Error = Infallible> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
     let (addr, fut) = warp::serve(routes).bind_with_graceful_shutdown(address, async move {
         token.cancelled().await;
-        log::info!("Shutting down API server");
+        tracing::info!("Shutting down API server");
     });
-    log::debug!("API started at: {}", addr);
+    tracing::debug!("API started at: {}", addr);
     fut.await;
 }
 
 #[cfg(test)]
 mod api_tests {
     use super::*;
-    use crate::storage::Database;
+    use crate::storage::InMemoryStorage;
     use crate::types::*;
     use rand::Rng;
+    use solana_program::pubkey::Pubkey;
     use std::net::SocketAddr;
     use std::str::FromStr;
     use tokio_test::assert_ok;
     use tokio_util::sync::CancellationToken;
 
+    const SOURCE: &str = "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g";
+    const DESTINATION: &str = "84YKYKo7qN54VHFLn6Eo5uBZMKzUY5Q9qB2t1L3drUeQ";
+
     #[tokio::test]
     async fn sanity_check() {
-        let path = "/tmp/api.json";
-        let mut db = Database::new(path).unwrap();
+        let mut db = InMemoryStorage::new();
         let token = CancellationToken::new();
-        let socket = SocketAddr::from_str("127.0.0.1:8080").unwrap();
+        let socket = SocketAddr::from_str("127.0.0.1:0").unwrap();
 
         // spawn API
         let db_move = db.clone();
         let token_move = token.clone();
-        let api_fut = tokio::spawn(async move { run_api(socket, db_move, token_move).await });
+        let api_fut = tokio::spawn(async move {
+            let routes = TestRoutes::with_storage(db_move).build();
+            run_api(socket, routes, token_move).await
+        });
 
         // add a block to the database
         let mut rng = rand::thread_rng();
         let block_height: u64 = rng.gen();
-        let source = String::from(format!("source{}", block_height));
-        let destination = String::from(format!("destination{}", block_height));
-        let block_hash = String::from(format!("block_hash{}", block_height));
+        let source = Address::from(Pubkey::new_unique());
+        let destination = Address::from(Pubkey::new_unique());
+        let block_hash = format!("block_hash{}", block_height);
 
         let amount = 100;
         let transaction = Transaction {
-            source: source.to_string(),
-            destination: destination.to_string(),
+            source,
+            destination,
             amount,
         };
 
         let block = Block {
             height: block_height,
+            slot: block_height,
             transactions: vec![transaction],
             hash: block_hash.to_string(),
             timestamp: 100100,
@@ -132,4 +900,365 @@ mod api_tests {
         let result = tokio::join!(api_fut).0;
         assert_ok!(result);
     }
+
+    #[tokio::test]
+    async fn transactions_pagination() {
+        let mut db = InMemoryStorage::new();
+        let transactions = (0..3)
+            .map(|amount| Transaction {
+                source: SOURCE.parse().unwrap(),
+                destination: DESTINATION.parse().unwrap(),
+                amount,
+            })
+            .collect();
+        let block = Block {
+            height: 1,
+            slot: 1,
+            hash: String::from("block_hash"),
+            timestamp: 100100,
+            transactions,
+        };
+        db.add_block(&block).await.unwrap();
+        let routes = TestRoutes::with_storage(db).build();
+
+        let response = warp::test::request()
+            .path(&format!(
+                "/transactions?address={}&limit=2&order=asc",
+                SOURCE
+            ))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), 200);
+        let page: TransactionPage = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(page.transactions.len(), 2);
+        assert_eq!(page.transactions[0].amount, 0);
+
+        let cursor = page.next_cursor.unwrap();
+        let response = warp::test::request()
+            .path(&format!(
+                "/transactions?address={}&limit=2&order=asc&cursor={}",
+                SOURCE, cursor
+            ))
+            .reply(&routes)
+            .await;
+        let page: TransactionPage = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(page.transactions.len(), 1);
+        assert_eq!(page.transactions[0].amount, 2);
+        assert_eq!(page.next_cursor, None);
+
+        // range filters
+        let response = warp::test::request()
+            .path(&format!("/transactions?address={}&from_slot=2", SOURCE))
+            .reply(&routes)
+            .await;
+        let page: TransactionPage = serde_json::from_slice(response.body()).unwrap();
+        assert!(page.transactions.is_empty());
+
+        // newest first when no order is given
+        let response = warp::test::request()
+            .path(&format!("/transactions?address={}", SOURCE))
+            .reply(&routes)
+            .await;
+        let page: TransactionPage = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(page.transactions[0].amount, 2);
+    }
+
+    #[tokio::test]
+    async fn account_at() {
+        let mut db = InMemoryStorage::new();
+        for height in 1..=2 {
+            let block = Block {
+                height,
+                slot: height,
+                hash: format!("block_hash{}", height),
+                timestamp: height as i64 * 100,
+                transactions: vec![Transaction {
+                    source: SOURCE.parse().unwrap(),
+                    destination: DESTINATION.parse().unwrap(),
+                    amount: 10,
+                }],
+            };
+            db.add_block(&block).await.unwrap();
+        }
+        let routes = TestRoutes::with_storage(db).build();
+
+        let response = warp::test::request()
+            .path(&format!(
+                "/account/history?address={}&interval=hour",
+                DESTINATION
+            ))
+            .reply(&routes)
+            .await;
+        let series: Vec<BalanceBucket> = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].inflow, 20);
+
+        for (at, balance) in [("", 20), ("&at=slot:1", 10), ("&at=time:99", 0)] {
+            let response = warp::test::request()
+                .path(&format!("/account?address={}{}", DESTINATION, at))
+                .reply(&routes)
+                .await;
+            let account: Account = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(account.balance, balance);
+        }
+    }
+
+    #[tokio::test]
+    async fn blocks() {
+        let mut db = InMemoryStorage::new();
+        for height in 1..=3 {
+            let block = Block {
+                height,
+                slot: height + 10,
+                hash: format!("block_hash{}", height),
+                timestamp: 100100,
+                transactions: vec![Transaction::default(); height as usize],
+            };
+            db.add_block(&block).await.unwrap();
+        }
+        let routes = TestRoutes::with_storage(db).build();
+
+        let response = warp::test::request().path("/blocks/2").reply(&routes).await;
+        let block: BlockInfo = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(block.hash, "block_hash2");
+        assert_eq!(block.transaction_count, 2);
+
+        let response = warp::test::request()
+            .path("/blocks/latest")
+            .reply(&routes)
+            .await;
+        let block: BlockInfo = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(block.height, 3);
+
+        let response = warp::test::request()
+            .path("/blocks?from=2&limit=1")
+            .reply(&routes)
+            .await;
+        let page: BlocksResponse = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(page.blocks.len(), 1);
+        assert_eq!(page.blocks[0].height, 2);
+        assert_eq!(page.next_height, Some(3));
+    }
+
+    #[tokio::test]
+    async fn batch() {
+        let mut db = InMemoryStorage::new();
+        let transactions = (1..=3)
+            .map(|amount| Transaction {
+                source: SOURCE.parse().unwrap(),
+                destination: DESTINATION.parse().unwrap(),
+                amount,
+            })
+            .collect();
+        let block = Block {
+            height: 1,
+            slot: 1,
+            hash: String::from("block_hash"),
+            timestamp: 100100,
+            transactions,
+        };
+        db.add_block(&block).await.unwrap();
+        let routes = TestRoutes::with_storage(db).build();
+        let missing = Address::from(Pubkey::new_unique());
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/accounts")
+            .json(&serde_json::json!({
+                "addresses": [DESTINATION, missing, SOURCE, DESTINATION]
+            }))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: AccountsResponse = serde_json::from_slice(response.body()).unwrap();
+        let balances: Vec<_> = body.accounts.iter().map(|a| a.balance).collect();
+        assert_eq!(balances, vec![6, -6]);
+        assert_eq!(body.not_found, vec![missing]);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/transactions/batch")
+            .json(&serde_json::json!({
+                "addresses": [SOURCE, DESTINATION],
+                "limit": 2,
+                "order": "asc"
+            }))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let pages: Vec<AddressTransactions> = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(pages.len(), 2);
+        let amounts: Vec<_> = pages[0].transactions.iter().map(|t| t.amount).collect();
+        assert_eq!(amounts, vec![1, 2]);
+        assert!(pages[0].next_cursor.is_some());
+        assert_eq!(pages[1].address.to_string(), DESTINATION);
+        assert!(pages[1].transactions.is_empty());
+
+        let addresses: Vec<_> = (0..=MAX_BATCH_TRANSACTIONS / MAX_PAGE_LIMIT)
+            .map(|_| Pubkey::new_unique().to_string())
+            .collect();
+        let response = warp::test::request()
+            .method("POST")
+            .path("/transactions/batch")
+            .json(&serde_json::json!({ "addresses": addresses, "limit": MAX_PAGE_LIMIT }))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = warp::test::request()
+            .method("POST")
+            .path("/transactions/batch")
+            .json(&serde_json::json!({ "addresses": addresses[1..], "limit": MAX_PAGE_LIMIT }))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let addresses: Vec<_> = (0..=MAX_BATCH_ADDRESSES)
+            .map(|_| Pubkey::new_unique().to_string())
+            .collect();
+        for (body, status) in [
+            (serde_json::json!({ "addresses": addresses }), 400),
+            (serde_json::json!({ "addresses": ["nope"] }), 400),
+            (serde_json::json!({}), 400),
+        ] {
+            let response = warp::test::request()
+                .method("POST")
+                .path("/accounts")
+                .json(&body)
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), status);
+            let body: ErrorBody = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(body.code, status);
+        }
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let mut db = InMemoryStorage::new();
+        let block = Block {
+            height: 1,
+            slot: 1,
+            hash: String::from("block_hash"),
+            timestamp: 100100,
+            transactions: vec![Transaction {
+                source: SOURCE.parse().unwrap(),
+                destination: DESTINATION.parse().unwrap(),
+                amount: 1,
+            }],
+        };
+        db.add_block(&block).await.unwrap();
+        let routes = TestRoutes::with_storage(db).build();
+
+        for (path, status) in [
+            (format!("/account?address={}", Pubkey::new_unique()), 404),
+            (format!("/transactions?address={}", DESTINATION), 404),
+            (String::from("/blocks/2"), 404),
+            (
+                format!(
+                    "/account/history?address={}&from_time=0&to_time=9999999999",
+                    SOURCE
+                ),
+                400,
+            ),
+            (format!("/account?address={}&at=yesterday", SOURCE), 400),
+            (format!("/transactions?address={}&cursor=nope", SOURCE), 400),
+            (String::from("/account?address=source"), 400),
+            (format!("/account?address={}", &SOURCE[1..]), 400),
+            (String::from("/transactions"), 400),
+            (String::from("/nothing/here"), 404),
+        ] {
+            let response = warp::test::request().path(&path).reply(&routes).await;
+            assert_eq!(response.status(), status, "{}", path);
+            let body: ErrorBody = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(body.code, status);
+            assert!(!body.message.is_empty());
+        }
+
+        let response = warp::test::request()
+            .path("/transactions?address=source")
+            .reply(&routes)
+            .await;
+        let body: ErrorBody = serde_json::from_slice(response.body()).unwrap();
+        assert!(body.message.contains("Invalid address: source"));
+
+        let response = warp::test::request()
+            .path("/stream/blocks")
+            .header("last-event-id", "abc")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: ErrorBody = serde_json::from_slice(response.body()).unwrap();
+        assert!(body.message.contains("last-event-id"));
+    }
+
+    /// Only failures to reach storage are worth retrying elsewhere
+    #[test]
+    fn error_statuses() {
+        for (error, status) in [
+            (Error::StorageError(String::from("locked")), 503),
+            (Error::ReadOnly, 403),
+            (Error::BlockExists(1), 500),
+            (Error::Inconsistent(String::from("Block 1 not found")), 500),
+            (Error::NotFound(String::from("Block 1")), 404),
+        ] {
+            assert_eq!(ApiError::from(error).status().as_u16(), status);
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_transactions() {
+        let feed = feed::block_feed();
+        let routes = TestRoutes::default().feed(feed.clone()).build();
+        // only WebSocket upgrades are served
+        for connection in [None, Some("keep-alive")] {
+            let mut request = warp::test::request().path("/stream/transactions");
+            if let Some(connection) = connection {
+                request = request.header("connection", connection);
+            }
+            let response = request.reply(&routes).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let body: ErrorBody = serde_json::from_slice(response.body()).unwrap();
+            assert!(body.message.to_lowercase().contains("connection"));
+        }
+
+        let mut client = warp::test::ws()
+            .path("/stream/transactions")
+            .handshake(routes)
+            .await
+            .unwrap();
+
+        client
+            .send_text(format!(
+                r#"{{"addresses": ["{}"], "min_amount": 10}}"#,
+                SOURCE
+            ))
+            .await;
+        // messages are handled in order, once this is answered the subscription is in place
+        client.send_text("not a subscription").await;
+        let body: ErrorBody =
+            serde_json::from_str(client.recv().await.unwrap().to_str().unwrap()).unwrap();
+        assert_eq!(body.code, 400);
+
+        let transfer = |source: &str, amount| Transaction {
+            source: source.parse().unwrap(),
+            destination: DESTINATION.parse().unwrap(),
+            amount,
+        };
+        let block = Block {
+            height: 1,
+            slot: 1,
+            hash: String::from("block_hash"),
+            timestamp: 100100,
+            transactions: vec![
+                transfer(SOURCE, 5),
+                transfer(&Pubkey::new_unique().to_string(), 50),
+                transfer(SOURCE, 20),
+            ],
+        };
+        feed.send(std::sync::Arc::new(block)).unwrap();
+
+        let message = client.recv().await.unwrap();
+        let transaction: Transaction = serde_json::from_str(message.to_str().unwrap()).unwrap();
+        assert_eq!(transaction.amount, 20);
+    }
 }
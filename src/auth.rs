@@ -0,0 +1,552 @@
+use crate::api::{ApiError, ErrorBody};
+use crate::error::{Error, Result};
+use crate::traits::Storage;
+use crate::types::ApiKey;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+/// Header clients pass their API key in
+pub const API_KEY_HEADER: &str = "x-api-key";
+/// Period an [ApiKey::quota] applies to
+pub const QUOTA_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Requests made with a key during the current quota window
+#[derive(Debug)]
+struct KeyUsage {
+    key: ApiKey,
+    window_start: Instant,
+    used: u64,
+}
+
+impl KeyUsage {
+    fn new(key: ApiKey) -> Self {
+        Self {
+            key,
+            window_start: Instant::now(),
+            used: 0,
+        }
+    }
+}
+
+/// Keys accepted by the API along with their quota usage, shared by every route
+/// Usage is kept in memory, quotas start afresh when the API restarts
+#[derive(Debug, Clone)]
+pub struct ApiKeys {
+    /// Shared as reloads can enable or disable authentication, see [ApiKeys::reload]
+    enabled: Arc<AtomicBool>,
+    keys: Arc<Mutex<HashMap<String, KeyUsage>>>,
+}
+
+impl ApiKeys {
+    /// Lets every request through, for deployments without access control
+    pub fn open() -> Self {
+        Self {
+            enabled: Arc::new(AtomicBool::new(false)),
+            keys: Arc::default(),
+        }
+    }
+
+    /// Only accepts requests made with one of `keys`, or one issued later on
+    pub fn new(keys: impl IntoIterator<Item = ApiKey>) -> Self {
+        let keys = keys
+            .into_iter()
+            .map(|key| (key.key.clone(), KeyUsage::new(key)))
+            .collect();
+        Self {
+            enabled: Arc::new(AtomicBool::new(true)),
+            keys: Arc::new(Mutex::new(keys)),
+        }
+    }
+
+    /// Keys from a JSON file holding an array of [ApiKey]s, along with those issued into storage
+    /// Without a file and any stored key, the API is left [ApiKeys::open]
+    pub async fn load<S: Storage>(file: Option<&Path>, storage: &S) -> Result<Self> {
+        let keys = load_keys(file, storage).await?;
+        if !enables(file, &keys) {
+            return Ok(Self::open());
+        }
+        Ok(Self::new(keys))
+    }
+
+    /// Replaces the accepted keys with those of the file and storage, as [ApiKeys::load] does
+    /// Lets replicas follow the keys issued and revoked by the process writing storage,
+    /// keys still accepted carry on with their quota usage. Authentication is enabled once
+    /// the writer stores a key, and disabled again once none is left
+    pub async fn reload<S: Storage>(&self, file: Option<&Path>, storage: &S) -> Result<()> {
+        let loaded = load_keys(file, storage).await?;
+        let mut keys = self.keys.lock().unwrap();
+        self.enabled
+            .store(enables(file, &loaded), Ordering::Relaxed);
+        let mut previous = std::mem::take(&mut *keys);
+        for key in loaded {
+            let usage = match previous.remove(&key.key) {
+                Some(usage) => KeyUsage { key, ..usage },
+                None => KeyUsage::new(key),
+            };
+            keys.insert(usage.key.key.clone(), usage);
+        }
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn insert(&self, key: ApiKey) {
+        let mut keys = self.keys.lock().unwrap();
+        keys.insert(key.key.clone(), KeyUsage::new(key));
+    }
+
+    /// Whether `key` is one the API accepts, never for [ApiKeys::open] as no key is checked
+    pub fn contains(&self, key: &str) -> bool {
+        self.is_enabled() && self.keys.lock().unwrap().contains_key(key)
+    }
+
+    /// Returns whether the key was known
+    pub fn remove(&self, key: &str) -> bool {
+        self.keys.lock().unwrap().remove(key).is_some()
+    }
+
+    /// Checks `key` is accepted, and admin if `admin` is set, then counts the request against it's quota
+    fn check(&self, key: Option<&str>, admin: bool) -> std::result::Result<(), ApiError> {
+        if !self.is_enabled() && !admin {
+            return Ok(());
+        }
+        let key = key.ok_or_else(|| {
+            ApiError::Unauthorized(format!("Missing API key, expected in {}", API_KEY_HEADER))
+        })?;
+        let mut keys = self.keys.lock().unwrap();
+        let usage = keys
+            .get_mut(key)
+            .ok_or_else(|| ApiError::Unauthorized(String::from("Invalid API key")))?;
+        if admin && !usage.key.admin {
+            return Err(ApiError::Forbidden(String::from("Admin API key required")));
+        }
+
+        let Some(quota) = usage.key.quota else {
+            return Ok(());
+        };
+        let elapsed = usage.window_start.elapsed();
+        if elapsed >= QUOTA_WINDOW {
+            usage.window_start = Instant::now();
+            usage.used = 0;
+        } else if usage.used >= quota {
+            let retry_after = (QUOTA_WINDOW - elapsed).as_secs().max(1);
+            return Err(ApiError::TooManyRequests(
+                format!("Quota of {} requests per day exhausted", quota),
+                retry_after,
+            ));
+        }
+        usage.used += 1;
+        Ok(())
+    }
+}
+
+/// Whether keys loaded from `file` and storage turn authentication on
+fn enables(file: Option<&Path>, keys: &[ApiKey]) -> bool {
+    file.is_some() || !keys.is_empty()
+}
+
+async fn load_keys<S: Storage>(file: Option<&Path>, storage: &S) -> Result<Vec<ApiKey>> {
+    let mut keys = storage.get_api_keys().await?;
+    if let Some(file) = file {
+        keys.extend(read_keys(file)?);
+    }
+    Ok(keys)
+}
+
+fn read_keys(file: &Path) -> Result<Vec<ApiKey>> {
+    let invalid = |error: String| {
+        Error::InvalidInput(format!(
+            "Failed to read API keys from {}: {}",
+            file.display(),
+            error
+        ))
+    };
+    let keys = std::fs::read_to_string(file).map_err(|error| invalid(error.to_string()))?;
+    serde_json::from_str(&keys).map_err(|error| invalid(error.to_string()))
+}
+
+/// Random 32 byte key, hex encoded
+fn generate_key() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Rejects requests without an accepted key, or whose key ran out of quota
+pub fn authorize(keys: ApiKeys) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    check(keys, false)
+}
+
+/// Like [authorize], also rejecting keys that aren't [ApiKey::admin]
+pub fn authorize_admin(keys: ApiKeys) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    check(keys, true)
+}
+
+/// Rejects requests changing keys or watches when `storage` is read-only, as on replicas
+/// Those are managed through the API of the process writing storage, replicas reload them
+pub fn writable<S: Storage>(storage: &S) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let read_only = storage.is_read_only();
+    warp::any()
+        .and_then(move || async move {
+            if read_only {
+                return Err(warp::reject::custom(ApiError::Forbidden(String::from(
+                    "Storage is read-only, manage keys and watches on the process writing it",
+                ))));
+            }
+            Ok(())
+        })
+        .untuple_one()
+}
+
+fn check(keys: ApiKeys, admin: bool) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>(API_KEY_HEADER)
+        .and_then(move |key: Option<String>| {
+            let keys = keys.clone();
+            async move {
+                keys.check(key.as_deref(), admin)
+                    .map_err(warp::reject::custom)
+            }
+        })
+        .untuple_one()
+}
+
+#[derive(Debug, Deserialize, Serialize, ToSchema)]
+pub struct IssueKeyRequest {
+    pub name: String,
+    /// Requests allowed per day, unlimited if omitted
+    pub quota: Option<u64>,
+    #[serde(default)]
+    pub admin: bool,
+}
+
+/// Issues a new API key
+#[utoipa::path(
+    post,
+    path = "/admin/keys",
+    tag = "admin",
+    request_body = IssueKeyRequest,
+    responses(
+        (status = 201, description = "Issued key", body = ApiKey),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Not an admin key, or read-only storage", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+async fn issue_key<S: Storage>(
+    request: IssueKeyRequest,
+    keys: ApiKeys,
+    mut storage_interface: S,
+) -> std::result::Result<impl Reply, Rejection> {
+    let key = ApiKey {
+        key: generate_key(),
+        name: request.name,
+        quota: request.quota,
+        admin: request.admin,
+    };
+    if let Err(error) = storage_interface.put_api_key(&key).await {
+        return Err(warp::reject::custom(ApiError::from(error)));
+    }
+    keys.insert(key.clone());
+    tracing::info!("Issued API key to {}", key.name);
+    Ok(warp::reply::with_status(
+        warp::reply::json(&key),
+        StatusCode::CREATED,
+    ))
+}
+
+/// Revokes an API key issued through the API
+#[utoipa::path(
+    delete,
+    path = "/admin/keys/{key}",
+    tag = "admin",
+    params(("key" = String, Path, description = "Key to revoke")),
+    responses(
+        (status = 204, description = "Key revoked"),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Not an admin key, or read-only storage", body = ErrorBody),
+        (status = 404, description = "Unknown key", body = ErrorBody),
+        (status = 409, description = "Key is loaded from the keys file", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+async fn revoke_key<S: Storage>(
+    key: String,
+    keys: ApiKeys,
+    mut storage_interface: S,
+) -> std::result::Result<impl Reply, Rejection> {
+    match storage_interface.remove_api_key(&key).await {
+        Ok(()) => {}
+        // keys from the file aren't stored, they'd be accepted again once it's loaded
+        Err(Error::NotFound(_)) if keys.contains(&key) => {
+            return Err(warp::reject::custom(ApiError::Conflict(String::from(
+                "API key is loaded from the keys file, remove it there",
+            ))));
+        }
+        Err(error) => return Err(warp::reject::custom(ApiError::from(error))),
+    }
+    keys.remove(&key);
+    tracing::info!("Revoked an API key");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Admin routes, all of them require an admin key and writable storage, see [writable]
+/// - POST /admin/keys - issues a key, see [IssueKeyRequest]
+/// - DELETE /admin/keys/<key> - revokes a key
+pub fn routes<S>(
+    db: S,
+    keys: ApiKeys,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    let db_move = db.clone();
+    let keys_move = keys.clone();
+    let issue_key_route = warp::path!("admin" / "keys")
+        .and(warp::post())
+        .and(authorize_admin(keys.clone()))
+        .and(writable(&db))
+        .and(warp::body::json())
+        .and(warp::any().map(move || keys_move.clone()))
+        .and(warp::any().map(move || db_move.clone()))
+        .and_then(issue_key);
+
+    let keys_move = keys.clone();
+    let revoke_key_route = warp::path!("admin" / "keys" / String)
+        .and(warp::delete())
+        .and(authorize_admin(keys))
+        .and(writable(&db))
+        .and(warp::any().map(move || keys_move.clone()))
+        .and(warp::any().map(move || db.clone()))
+        .and_then(revoke_key);
+
+    issue_key_route.or(revoke_key_route)
+}
+
+#[cfg(test)]
+mod auth_tests {
+    use crate::api;
+    use crate::auth::*;
+    use crate::storage::{Database, InMemoryStorage, Replica};
+
+    fn key(key: &str, quota: Option<u64>, admin: bool) -> ApiKey {
+        ApiKey {
+            key: String::from(key),
+            name: String::from(key),
+            quota,
+            admin,
+        }
+    }
+
+    #[tokio::test]
+    async fn quota() {
+        let keys = ApiKeys::new([key("limited", Some(2), false)]);
+        let routes = api::TestRoutes::default().keys(keys).build();
+
+        let response = warp::test::request().path("/blocks").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = warp::test::request()
+            .path("/blocks")
+            .header(API_KEY_HEADER, "unknown")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        for _ in 0..2 {
+            let response = warp::test::request()
+                .path("/blocks")
+                .header(API_KEY_HEADER, "limited")
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = warp::test::request()
+            .path("/blocks")
+            .header(API_KEY_HEADER, "limited")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key("retry-after"));
+        let body: ErrorBody = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body.code, 429);
+    }
+
+    #[tokio::test]
+    async fn issue_and_revoke() {
+        let storage = InMemoryStorage::new();
+        let keys = ApiKeys::new([key("admin", None, true), key("user", None, false)]);
+        let routes = api::TestRoutes::with_storage(storage.clone())
+            .keys(keys)
+            .build();
+
+        let request = IssueKeyRequest {
+            name: String::from("partner"),
+            quota: Some(100),
+            admin: false,
+        };
+        let response = warp::test::request()
+            .method("POST")
+            .path("/admin/keys")
+            .header(API_KEY_HEADER, "user")
+            .json(&request)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = warp::test::request()
+            .method("POST")
+            .path("/admin/keys")
+            .header(API_KEY_HEADER, "admin")
+            .json(&request)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let issued: ApiKey = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(issued.quota, Some(100));
+        assert_eq!(storage.get_api_keys().await.unwrap(), vec![issued.clone()]);
+
+        let response = warp::test::request()
+            .path("/blocks")
+            .header(API_KEY_HEADER, &issued.key)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let revoke = |key: &str| {
+            warp::test::request()
+                .method("DELETE")
+                .path(&format!("/admin/keys/{}", key))
+                .header(API_KEY_HEADER, "admin")
+        };
+        let response = revoke(&issued.key).reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(storage.get_api_keys().await.unwrap().is_empty());
+        let response = revoke(&issued.key).reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        // keys from the file can only be removed there
+        let response = revoke("user").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let response = warp::test::request()
+            .path("/blocks")
+            .header(API_KEY_HEADER, "user")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = warp::test::request()
+            .path("/blocks")
+            .header(API_KEY_HEADER, &issued.key)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn load() {
+        let storage = InMemoryStorage::new();
+        assert!(!ApiKeys::load(None, &storage).await.unwrap().is_enabled());
+
+        let path = std::env::temp_dir().join(format!("keys-{}.json", generate_key()));
+        std::fs::write(&path, r#"[{"key": "file", "name": "ops", "admin": true}]"#).unwrap();
+        let mut storage_move = storage.clone();
+        storage_move
+            .put_api_key(&key("stored", None, false))
+            .await
+            .unwrap();
+
+        let keys = ApiKeys::load(Some(&path), &storage).await.unwrap();
+        assert!(keys.check(Some("file"), true).is_ok());
+        assert!(keys.check(Some("stored"), false).is_ok());
+        crate::storage::remove_database(path);
+
+        // stored keys alone enable authentication
+        let keys = ApiKeys::load(None, &storage).await.unwrap();
+        assert!(keys.is_enabled());
+        assert!(keys.check(None, false).is_err());
+    }
+
+    #[tokio::test]
+    async fn replica() {
+        let path = std::env::temp_dir().join(format!("storage-{}.json", generate_key()));
+        let path = path.to_str().unwrap();
+        let mut writer = Database::new(path).unwrap();
+        writer.put_api_key(&key("admin", None, true)).await.unwrap();
+        let replica = Replica::open(path).unwrap();
+        let keys = ApiKeys::load(None, &replica).await.unwrap();
+        let routes = api::TestRoutes::with_storage(replica.clone())
+            .keys(keys.clone())
+            .build();
+
+        // keys are managed on the writer, replicas turn changes away without touching their own
+        let request = IssueKeyRequest {
+            name: String::from("partner"),
+            quota: None,
+            admin: false,
+        };
+        let response = warp::test::request()
+            .method("POST")
+            .path("/admin/keys")
+            .header(API_KEY_HEADER, "admin")
+            .json(&request)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body: ErrorBody = serde_json::from_slice(response.body()).unwrap();
+        assert!(body.message.contains("read-only"));
+        let response = warp::test::request()
+            .method("DELETE")
+            .path("/admin/keys/admin")
+            .header(API_KEY_HEADER, "admin")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(keys.check(Some("admin"), true).is_ok());
+
+        // and pick up those made on the writer once reloaded
+        writer
+            .put_api_key(&key("issued", None, false))
+            .await
+            .unwrap();
+        writer.remove_api_key("admin").await.unwrap();
+        keys.reload(None, &replica).await.unwrap();
+        assert!(keys.check(Some("issued"), false).is_ok());
+        assert!(keys.check(Some("admin"), true).is_err());
+        crate::storage::remove_database(path);
+    }
+
+    /// Replicas started before any key was issued close once the writer stores one
+    #[tokio::test]
+    async fn replica_enabled() {
+        let path = std::env::temp_dir().join(format!("storage-{}.json", generate_key()));
+        let path = path.to_str().unwrap();
+        let mut writer = Database::new(path).unwrap();
+        let replica = Replica::open(path).unwrap();
+        let keys = ApiKeys::load(None, &replica).await.unwrap();
+        assert!(!keys.is_enabled());
+        assert!(keys.check(None, false).is_ok());
+
+        writer
+            .put_api_key(&key("issued", None, false))
+            .await
+            .unwrap();
+        keys.reload(None, &replica).await.unwrap();
+        assert!(keys.is_enabled());
+        assert!(keys.check(None, false).is_err());
+        assert!(keys.check(Some("issued"), false).is_ok());
+
+        // and open again once the last one is revoked
+        writer.remove_api_key("issued").await.unwrap();
+        keys.reload(None, &replica).await.unwrap();
+        assert!(!keys.is_enabled());
+        assert!(keys.check(None, false).is_ok());
+        crate::storage::remove_database(path);
+    }
+}
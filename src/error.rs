@@ -10,15 +10,24 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Solana RPC Client error: {0}")]
-    RpcError(ClientError),
+    RpcError(Box<ClientError>),
     #[error("Solana PubSub Client error: {0}")]
-    PubSubError(PubsubClientError),
+    PubSubError(Box<PubsubClientError>),
     #[error("Channel Failed: {0} - Failure: {1}")]
     ChannelFailed(String, String),
     #[error("Termination Occured")]
     Termination,
+    /// Storage couldn't be read or written, expected to be transient
     #[error("Storage Error: {0}")]
     StorageError(String),
+    /// Stored data contradicts itself, e.g. an index pointing at a missing block
+    #[error("Storage Inconsistency: {0}")]
+    Inconsistent(String),
+    #[error("Block {0} exists")]
+    BlockExists(u64),
+    /// Writes are refused as storage is written by another process
+    #[error("Storage is read-only")]
+    ReadOnly,
     #[error("Var Error: {0}")]
     VarError(String),
     #[error("Slot Skipped: {0}")]
@@ -27,6 +36,10 @@ pub enum Error {
     SlotMissing(Slot),
     #[error("Join Error: {0}")]
     JoinError(String),
+    #[error("Invalid Input: {0}")]
+    InvalidInput(String),
+    #[error("{0}")]
+    NotFound(String),
 }
 
 impl From<VarError> for Error {
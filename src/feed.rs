@@ -0,0 +1,240 @@
+use crate::api::ErrorBody;
+use crate::traits::Storage;
+use crate::types::*;
+use futures_util::{SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use utoipa::ToSchema;
+use warp::filters::sse::Event;
+use warp::filters::ws::{Message, WebSocket};
+
+/// Number of blocks a subscriber can fall behind before it is dropped
+pub const FEED_CAPACITY: usize = 1024;
+
+/// Fans blocks out to live subscribers as they are committed to storage
+/// Sending never waits on subscribers, lagging ones miss blocks and are dropped
+pub type BlockFeed = broadcast::Sender<Arc<Block>>;
+
+/// Blocks read from storage at a time when replaying missed blocks to a stream
+pub const REPLAY_PAGE: usize = 100;
+/// Events buffered for a block stream before it falls behind the feed
+const STREAM_BUFFER: usize = 64;
+
+pub fn block_feed() -> BlockFeed {
+    broadcast::channel(FEED_CAPACITY).0
+}
+
+/// Filter sent by a client to select the transactions it receives
+/// Sending another one replaces it
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize, ToSchema)]
+pub struct Subscription {
+    /// Matches transactions from or to any of these, every transaction if empty
+    #[serde(default)]
+    pub addresses: HashSet<Address>,
+    #[serde(default)]
+    pub min_amount: u64,
+}
+
+impl Subscription {
+    pub fn matches(&self, transaction: &Transaction) -> bool {
+        transaction.amount >= self.min_amount
+            && (self.addresses.is_empty()
+                || self.addresses.contains(&transaction.source)
+                || self.addresses.contains(&transaction.destination))
+    }
+}
+
+/// Streams transactions matching the client's [Subscription] over a WebSocket
+/// Nothing is sent until the client subscribes
+pub async fn serve_transactions(socket: WebSocket, mut blocks: broadcast::Receiver<Arc<Block>>) {
+    let (mut tx, mut rx) = socket.split();
+    let mut subscription: Option<Subscription> = None;
+
+    loop {
+        tokio::select! {
+            message = rx.next() => {
+                let message = match message {
+                    Some(Ok(message)) => message,
+                    // client closed the socket or it failed
+                    _ => return,
+                };
+                if message.is_close() {
+                    return;
+                }
+                let Ok(text) = message.to_str() else {
+                    continue;
+                };
+
+                match serde_json::from_str::<Subscription>(text) {
+                    Ok(filter) => {
+                        tracing::debug!("WebSocket subscribed: {:?}", filter);
+                        subscription = Some(filter);
+                    }
+                    Err(error) => {
+                        let body = ErrorBody {
+                            code: 400,
+                            message: format!("Invalid subscription: {}", error),
+                        };
+                        let body = serde_json::to_string(&body).unwrap_or_default();
+                        if tx.send(Message::text(body)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            block = blocks.recv() => {
+                let block = match block {
+                    Ok(block) => block,
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!("Dropping WebSocket subscriber, {} blocks behind", missed);
+                        let _ = tx.send(Message::close_with(1008u16, "lagging behind")).await;
+                        return;
+                    }
+                    Err(RecvError::Closed) => {
+                        let _ = tx.send(Message::close()).await;
+                        return;
+                    }
+                };
+                let Some(subscription) = &subscription else {
+                    continue;
+                };
+
+                for transaction in &block.transactions {
+                    if !subscription.matches(transaction) {
+                        continue;
+                    }
+                    let text = serde_json::to_string(transaction).unwrap_or_default();
+                    if tx.send(Message::text(text)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn block_event(block: &Block) -> Event {
+    Event::default()
+        .id(block.height.to_string())
+        .event("block")
+        .json_data(BlockSummary::from(block))
+        .unwrap_or_default()
+}
+
+/// Server-Sent Events of a [BlockSummary] for each stored block, with the height as event id
+/// With `last_event_id` the stored blocks above that height are replayed first
+/// A stream falling behind the feed ends, the client resumes by reconnecting with it's last id
+pub fn block_events<S>(
+    storage: S,
+    mut blocks: broadcast::Receiver<Arc<Block>>,
+    last_event_id: Option<u64>,
+) -> impl Stream<Item = Result<Event, Infallible>>
+where
+    S: Storage + Sync + 'static,
+{
+    let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+    tokio::spawn(async move {
+        // `blocks` is subscribed before replaying, so nothing is missed in between
+        let mut last_replayed = None;
+        let mut query = BlockQuery {
+            from: last_event_id.map(|height| height.saturating_add(1)),
+            to: None,
+            limit: REPLAY_PAGE,
+        };
+        while query.from.is_some() {
+            let page = match storage.get_blocks(&query).await {
+                Ok(page) => page,
+                Err(error) => {
+                    tracing::warn!("Block stream replay failed: {}", error);
+                    break;
+                }
+            };
+            for block in &page.blocks {
+                last_replayed = Some(block.height);
+                if tx.send(Ok(block_event(block))).await.is_err() {
+                    return;
+                }
+            }
+            query.from = page.next_height;
+        }
+
+        loop {
+            let block = match blocks.recv().await {
+                Ok(block) => block,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("Dropping block stream, {} blocks behind", missed);
+                    return;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            if last_replayed.is_some_and(|height| block.height <= height) {
+                continue;
+            }
+            if tx.send(Ok(block_event(&block))).await.is_err() {
+                return;
+            }
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+#[cfg(test)]
+mod feed_tests {
+    use crate::feed::*;
+    use crate::storage::InMemoryStorage;
+    use futures_util::StreamExt;
+
+    fn block(height: u64) -> Block {
+        Block {
+            height,
+            slot: height + 10,
+            hash: format!("block_hash{}", height),
+            timestamp: 100100,
+            transactions: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn block_events_resume() {
+        let mut storage = InMemoryStorage::new();
+        for height in 1..=3 {
+            storage.add_block(&block(height)).await.unwrap();
+        }
+        let feed = block_feed();
+        let events = block_events(storage.clone(), feed.subscribe(), Some(1));
+        tokio::pin!(events);
+
+        // block 3 is both stored and on the feed, it's only sent once
+        feed.send(Arc::new(block(3))).unwrap();
+        feed.send(Arc::new(block(4))).unwrap();
+
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let event = events.next().await.unwrap().unwrap().to_string();
+            let id = event
+                .lines()
+                .find_map(|line| line.strip_prefix("id:"))
+                .unwrap()
+                .to_string();
+            ids.push(id);
+        }
+        assert_eq!(ids, vec!["2", "3", "4"]);
+    }
+
+    #[tokio::test]
+    async fn block_events_live() {
+        let feed = block_feed();
+        let events = block_events(InMemoryStorage::new(), feed.subscribe(), None);
+        tokio::pin!(events);
+
+        feed.send(Arc::new(block(7))).unwrap();
+        let event = events.next().await.unwrap().unwrap().to_string();
+        assert!(event.contains("event:block"));
+        assert!(event.contains(r#""transaction_count":0"#));
+    }
+}
@@ -0,0 +1,435 @@
+use crate::api::{DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT};
+use crate::error::{self, Error};
+use crate::traits::Storage;
+use crate::types::{self, Account, Address, Block, BlockQuery, Cursor, TransactionQuery};
+use async_graphql::connection::{query, Connection, CursorType, Edge};
+use async_graphql::http::GraphiQLSource;
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Enum, Object, Result, Schema};
+use async_graphql_warp::GraphQLResponse;
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use warp::{Filter, Rejection, Reply};
+
+/// Deepest query accepted, accounts and counterparties can otherwise be nested endlessly
+pub const MAX_QUERY_DEPTH: usize = 10;
+/// Most fields a query can resolve, counting the fields of every edge a page can hold
+pub const MAX_QUERY_COMPLEXITY: usize = 50_000;
+/// Transfers a block is assumed to hold when costing its `transactions`
+const BLOCK_TRANSACTIONS_COST: usize = 100;
+
+async_graphql::scalar!(
+    Address,
+    "Address",
+    "Base58 encoded 32 byte Solana public key"
+);
+
+impl CursorType for Cursor {
+    type Error = Error;
+
+    fn decode_cursor(cursor: &str) -> std::result::Result<Self, Self::Error> {
+        cursor.parse()
+    }
+
+    fn encode_cursor(&self) -> String {
+        self.to_string()
+    }
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(name = "Order", remote = "types::Order")]
+enum Order {
+    /// Newest first
+    Desc,
+    /// Oldest first
+    Asc,
+}
+
+fn page_limit(first: Option<usize>) -> usize {
+    first.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT)
+}
+
+/// Cost of a connection, its selection is resolved for every edge of the page
+fn connection_complexity(first: Option<i32>, child_complexity: usize) -> usize {
+    let first = first.map(|first| first.max(0) as usize);
+    page_limit(first).saturating_mul(child_complexity)
+}
+
+/// Nullable lookups resolve to `null` rather than an error when nothing is stored
+fn found<T>(result: error::Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(Error::NotFound(_)) => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+pub struct AccountNode<S> {
+    account: Account,
+    storage: S,
+}
+
+#[Object(name = "Account")]
+impl<S> AccountNode<S>
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    async fn address(&self) -> Address {
+        self.account.address
+    }
+
+    /// Lamports, can be negative as only transfers seen by the aggregator are accounted for
+    async fn balance(&self) -> i64 {
+        self.account.balance
+    }
+
+    /// Transfers sent by this account
+    #[graphql(complexity = "connection_complexity(first, child_complexity)")]
+    async fn transactions(
+        &self,
+        after: Option<String>,
+        first: Option<i32>,
+        order: Option<Order>,
+    ) -> Result<Connection<Cursor, TransactionNode<S>>> {
+        query(after, None, first, None, |after, _, first, _| async move {
+            let query = TransactionQuery {
+                limit: page_limit(first),
+                cursor: after,
+                order: order.map(Into::into).unwrap_or_default(),
+                ..Default::default()
+            };
+            let page = found(
+                self.storage
+                    .get_transactions(&self.account.address, &query)
+                    .await,
+            )?
+            .unwrap_or_default();
+
+            let mut connection = Connection::new(after.is_some(), page.next_cursor.is_some());
+            let edges = page.cursors.into_iter().zip(page.transactions);
+            connection.edges.extend(edges.map(|(cursor, transaction)| {
+                let node = TransactionNode {
+                    transaction,
+                    storage: self.storage.clone(),
+                };
+                Edge::new(cursor, node)
+            }));
+            Ok::<_, async_graphql::Error>(connection)
+        })
+        .await
+    }
+}
+
+pub struct TransactionNode<S> {
+    transaction: types::Transaction,
+    storage: S,
+}
+
+impl<S: Storage + Clone> TransactionNode<S> {
+    async fn account(&self, address: &Address) -> Result<Option<AccountNode<S>>> {
+        let account = found(self.storage.get_account(address).await)?;
+        Ok(account.map(|account| AccountNode {
+            account,
+            storage: self.storage.clone(),
+        }))
+    }
+}
+
+#[Object(name = "Transaction")]
+impl<S> TransactionNode<S>
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    async fn source(&self) -> Address {
+        self.transaction.source
+    }
+
+    async fn destination(&self) -> Address {
+        self.transaction.destination
+    }
+
+    async fn amount(&self) -> u64 {
+        self.transaction.amount
+    }
+
+    async fn source_account(&self) -> Result<Option<AccountNode<S>>> {
+        self.account(&self.transaction.source).await
+    }
+
+    async fn destination_account(&self) -> Result<Option<AccountNode<S>>> {
+        self.account(&self.transaction.destination).await
+    }
+}
+
+pub struct BlockNode<S> {
+    block: Block,
+    storage: S,
+}
+
+#[Object(name = "Block")]
+impl<S> BlockNode<S>
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    async fn height(&self) -> u64 {
+        self.block.height
+    }
+
+    async fn slot(&self) -> u64 {
+        self.block.slot
+    }
+
+    async fn hash(&self) -> &str {
+        &self.block.hash
+    }
+
+    /// Unix seconds
+    async fn timestamp(&self) -> i64 {
+        self.block.timestamp
+    }
+
+    async fn transaction_count(&self) -> usize {
+        self.block.transactions.len()
+    }
+
+    #[graphql(complexity = "BLOCK_TRANSACTIONS_COST * child_complexity")]
+    async fn transactions(&self) -> Vec<TransactionNode<S>> {
+        self.block
+            .transactions
+            .iter()
+            .map(|transaction| TransactionNode {
+                transaction: transaction.clone(),
+                storage: self.storage.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Resolvers only go through [Storage], which is held in the schema data
+pub struct QueryRoot<S>(PhantomData<S>);
+
+impl<S: Storage + Clone> QueryRoot<S> {
+    fn block_node(storage: &S, block: Option<Block>) -> Option<BlockNode<S>> {
+        block.map(|block| BlockNode {
+            block,
+            storage: storage.clone(),
+        })
+    }
+}
+
+#[Object(name = "Query")]
+impl<S> QueryRoot<S>
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    async fn account(&self, ctx: &Context<'_>, address: Address) -> Result<Option<AccountNode<S>>> {
+        let storage = ctx.data_unchecked::<S>();
+        let account = found(storage.get_account(&address).await)?;
+        Ok(account.map(|account| AccountNode {
+            account,
+            storage: storage.clone(),
+        }))
+    }
+
+    async fn block(&self, ctx: &Context<'_>, height: u64) -> Result<Option<BlockNode<S>>> {
+        let storage = ctx.data_unchecked::<S>();
+        let block = found(storage.get_block(height).await)?;
+        Ok(Self::block_node(storage, block))
+    }
+
+    async fn latest_block(&self, ctx: &Context<'_>) -> Result<Option<BlockNode<S>>> {
+        let storage = ctx.data_unchecked::<S>();
+        let block = found(storage.get_latest_block().await)?;
+        Ok(Self::block_node(storage, block))
+    }
+
+    /// Stored blocks in ascending height, `from` and `to` are inclusive
+    #[graphql(complexity = "connection_complexity(first, child_complexity)")]
+    async fn blocks(
+        &self,
+        ctx: &Context<'_>,
+        from: Option<u64>,
+        to: Option<u64>,
+        after: Option<String>,
+        first: Option<i32>,
+    ) -> Result<Connection<u64, BlockNode<S>>> {
+        let storage = ctx.data_unchecked::<S>();
+        query(
+            after,
+            None,
+            first,
+            None,
+            |after: Option<u64>, _, first, _| async move {
+                let query = BlockQuery {
+                    from: after.map(|height| height.saturating_add(1)).max(from),
+                    to,
+                    limit: page_limit(first),
+                };
+                let page = storage.get_blocks(&query).await?;
+
+                let mut connection = Connection::new(after.is_some(), page.next_height.is_some());
+                connection
+                    .edges
+                    .extend(page.blocks.into_iter().map(|block| {
+                        let height = block.height;
+                        let node = BlockNode {
+                            block,
+                            storage: storage.clone(),
+                        };
+                        Edge::new(height, node)
+                    }));
+                Ok::<_, async_graphql::Error>(connection)
+            },
+        )
+        .await
+    }
+}
+
+pub type ApiSchema<S> = Schema<QueryRoot<S>, EmptyMutation, EmptySubscription>;
+
+pub fn schema<S>(storage: S) -> ApiSchema<S>
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    Schema::build(QueryRoot(PhantomData), EmptyMutation, EmptySubscription)
+        .data(storage)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .limit_complexity(MAX_QUERY_COMPLEXITY)
+        .finish()
+}
+
+/// - /graphql - GraphQL endpoint
+/// - /graphiql - GraphiQL IDE for the endpoint
+pub fn routes<S>(storage: S) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    let graphql_route = warp::path!("graphql")
+        .and(async_graphql_warp::graphql(schema(storage)))
+        .and_then(
+            |(schema, request): (ApiSchema<S>, async_graphql::Request)| async move {
+                Ok::<_, Infallible>(GraphQLResponse::from(schema.execute(request).await))
+            },
+        );
+
+    let graphiql_route = warp::path!("graphiql")
+        .and(warp::get())
+        .map(|| warp::reply::html(GraphiQLSource::build().endpoint("/graphql").finish()));
+
+    graphiql_route.or(graphql_route)
+}
+
+#[cfg(test)]
+mod graphql_tests {
+    use crate::graphql::*;
+    use crate::storage::InMemoryStorage;
+    use crate::types::Transaction;
+    use solana_program::pubkey::Pubkey;
+
+    #[tokio::test]
+    async fn account_transactions() {
+        let mut storage = InMemoryStorage::new();
+        let source = Address::from(Pubkey::new_unique());
+        let destination = Address::from(Pubkey::new_unique());
+        for height in 1..=3 {
+            let block = Block {
+                height,
+                slot: height,
+                hash: format!("block_hash{}", height),
+                timestamp: 100100,
+                transactions: vec![Transaction {
+                    source,
+                    destination,
+                    amount: height,
+                }],
+            };
+            storage.add_block(&block).await.unwrap();
+        }
+        let schema = schema(storage);
+
+        let query = format!(
+            r#"{{
+                account(address: "{}") {{
+                    balance
+                    transactions(first: 2) {{
+                        edges {{ node {{ amount destinationAccount {{ balance }} }} }}
+                        pageInfo {{ hasNextPage endCursor }}
+                    }}
+                }}
+                latestBlock {{ height transactionCount }}
+                blocks(from: 2) {{ edges {{ cursor node {{ hash }} }} }}
+                missing: account(address: "{}") {{ balance }}
+            }}"#,
+            source,
+            Pubkey::new_unique()
+        );
+        let response = schema.execute(query).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+        let data = response.data.into_json().unwrap();
+        assert!(data["missing"].is_null());
+        assert_eq!(data["latestBlock"]["height"], 3);
+        assert_eq!(data["latestBlock"]["transactionCount"], 1);
+        let blocks = data["blocks"]["edges"].as_array().unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0]["cursor"], "2");
+        assert_eq!(blocks[0]["node"]["hash"], "block_hash2");
+
+        let account = &data["account"];
+        assert_eq!(account["balance"], -6);
+        let edges = account["transactions"]["edges"].as_array().unwrap();
+        assert_eq!(edges.len(), 2);
+        assert_eq!(edges[0]["node"]["amount"], 3);
+        assert_eq!(edges[0]["node"]["destinationAccount"]["balance"], 6);
+        assert_eq!(account["transactions"]["pageInfo"]["hasNextPage"], true);
+
+        // resume after the last edge of the first page
+        let cursor = account["transactions"]["pageInfo"]["endCursor"]
+            .as_str()
+            .unwrap();
+        let query = format!(
+            r#"{{ account(address: "{}") {{ transactions(first: 2, after: "{}") {{
+                edges {{ node {{ amount }} }}
+                pageInfo {{ hasNextPage }}
+            }} }} }}"#,
+            source, cursor
+        );
+        let data = schema.execute(query).await.data.into_json().unwrap();
+        let transactions = &data["account"]["transactions"];
+        assert_eq!(transactions["edges"].as_array().unwrap().len(), 1);
+        assert_eq!(transactions["edges"][0]["node"]["amount"], 1);
+        assert_eq!(transactions["pageInfo"]["hasNextPage"], false);
+    }
+
+    /// Nested pages multiply, a query fanning out to more fields than allowed is rejected upfront
+    #[tokio::test]
+    async fn complexity() {
+        let schema = schema(InMemoryStorage::new());
+        let nested = |first: usize| {
+            format!(
+                r#"{{ account(address: "{}") {{ transactions(first: {first}) {{ edges {{ node {{
+                    destinationAccount {{ transactions(first: {first}) {{ edges {{ node {{ amount }} }} }} }}
+                }} }} }} }} }}"#,
+                Pubkey::new_unique()
+            )
+        };
+        let response = schema.execute(nested(1000)).await;
+        assert_eq!(response.errors.len(), 1);
+        assert!(response.errors[0].message.contains("too complex"));
+        let response = schema.execute(nested(10)).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+        let response = schema
+            .execute("{ blocks(first: 1000) { edges { node { transactions { sourceAccount { balance } } } } } }")
+            .await;
+        assert!(response.errors[0].message.contains("too complex"));
+    }
+
+    #[tokio::test]
+    async fn invalid_address() {
+        let schema = schema(InMemoryStorage::new());
+        let response = schema
+            .execute(r#"{ account(address: "nope") { balance } }"#)
+            .await;
+        assert_eq!(response.errors.len(), 1);
+    }
+}
@@ -0,0 +1,382 @@
+use crate::error::Error;
+use crate::metrics::Metrics;
+use crate::traits::Storage;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+/// Slots storage can trail the chain by and still be ready, about a minute of slots
+pub const DEFAULT_MAX_INGESTION_LAG: u64 = 150;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskStatus {
+    Running,
+    /// Failed and waiting to be restarted, see [crate::supervisor]
+    Restarting,
+    /// Failed too many times to be restarted
+    Failed,
+    Stopped,
+}
+
+/// Status of a task along with its failures
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, ToSchema)]
+pub struct TaskState {
+    pub status: TaskStatus,
+    /// Times the task was restarted after failing
+    pub restarts: u64,
+    /// Error the task last failed with
+    pub last_error: Option<String>,
+}
+
+/// Liveness and readiness of the aggregator, judged from its tasks, storage and [Metrics]
+/// Cheap to clone, clones share the same state
+#[derive(Clone, Debug)]
+pub struct Health {
+    metrics: Metrics,
+    max_lag: u64,
+    /// Whether this process ingests blocks, otherwise only storage is checked for readiness
+    ingesting: bool,
+    tasks: Arc<Mutex<BTreeMap<String, TaskState>>>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new(Metrics::new(), DEFAULT_MAX_INGESTION_LAG)
+    }
+}
+
+impl Health {
+    /// `max_lag` is the ingestion lag in slots, over which the aggregator isn't ready
+    pub fn new(metrics: Metrics, max_lag: u64) -> Self {
+        Self {
+            metrics,
+            max_lag,
+            ingesting: true,
+            tasks: Arc::default(),
+        }
+    }
+
+    /// For processes serving the API without ingesting, readiness then only depends on storage
+    pub fn without_ingestion(self) -> Self {
+        Self {
+            ingesting: false,
+            ..self
+        }
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Reports the task as running until the returned guard is dropped
+    /// Move the guard into the task, so it's dropped when the task returns or panics
+    pub fn task(&self, name: &str) -> TaskGuard {
+        let state = TaskState {
+            status: TaskStatus::Running,
+            restarts: 0,
+            last_error: None,
+        };
+        self.tasks.lock().unwrap().insert(name.to_string(), state);
+        TaskGuard {
+            name: name.to_string(),
+            tasks: self.tasks.clone(),
+        }
+    }
+
+    /// State of every task by name
+    pub fn tasks(&self) -> BTreeMap<String, TaskState> {
+        self.tasks.lock().unwrap().clone()
+    }
+
+    /// Alive as long as every task is running, or about to be restarted
+    pub fn liveness(&self) -> Liveness {
+        let tasks: BTreeMap<String, TaskStatus> = self
+            .tasks()
+            .into_iter()
+            .map(|(name, state)| (name, state.status))
+            .collect();
+        Liveness {
+            alive: tasks
+                .values()
+                .all(|status| matches!(status, TaskStatus::Running | TaskStatus::Restarting)),
+            tasks,
+        }
+    }
+
+    /// Ready once storage is open, the slot subscription is live and storage is caught up
+    pub async fn readiness<S: Storage>(&self, storage: &S) -> Readiness {
+        let storage = match storage.get_latest_block().await {
+            // an empty storage is open all the same
+            Ok(_) | Err(Error::NotFound(_)) => ComponentStatus::ready("open"),
+            Err(error) => ComponentStatus::not_ready(error.to_string()),
+        };
+        if !self.ingesting {
+            return Readiness {
+                ready: storage.ready,
+                storage,
+                monitor: ComponentStatus::ready("not ingesting"),
+                ingestion: ComponentStatus::ready("not ingesting"),
+            };
+        }
+
+        let monitor = match self.metrics.monitor_subscribed.get() {
+            1 => ComponentStatus::ready("slot subscription live"),
+            _ => ComponentStatus::not_ready("slot subscription not live"),
+        };
+
+        let ingestion = match self.metrics.ingestion_lag() {
+            Some(lag) if lag <= self.max_lag => {
+                ComponentStatus::ready(format!("{} slots behind", lag))
+            }
+            Some(lag) => ComponentStatus::not_ready(format!(
+                "{} slots behind, over the {} allowed",
+                lag, self.max_lag
+            )),
+            None => ComponentStatus::not_ready("no block stored since a slot was notified"),
+        };
+
+        Readiness {
+            ready: storage.ready && monitor.ready && ingestion.ready,
+            storage,
+            monitor,
+            ingestion,
+        }
+    }
+}
+
+/// Reports the status of a task, and reports it stopped once dropped, see [Health::task]
+#[derive(Debug)]
+pub struct TaskGuard {
+    name: String,
+    tasks: Arc<Mutex<BTreeMap<String, TaskState>>>,
+}
+
+impl TaskGuard {
+    fn update(&self, update: impl FnOnce(&mut TaskState)) {
+        if let Ok(mut tasks) = self.tasks.lock() {
+            if let Some(state) = tasks.get_mut(&self.name) {
+                update(state);
+            }
+        }
+    }
+
+    /// The task is running again after a restart
+    pub fn running(&self) {
+        self.update(|state| state.status = TaskStatus::Running);
+    }
+
+    /// The task failed with `error` and is about to be restarted
+    pub fn restarting(&self, error: String) {
+        self.update(|state| {
+            state.status = TaskStatus::Restarting;
+            state.restarts += 1;
+            state.last_error = Some(error);
+        });
+    }
+
+    /// The task failed with `error` and won't be restarted
+    pub fn failed(&self, error: String) {
+        self.update(|state| {
+            state.status = TaskStatus::Failed;
+            state.last_error = Some(error);
+        });
+    }
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.update(|state| {
+            if state.status != TaskStatus::Failed {
+                state.status = TaskStatus::Stopped;
+            }
+        });
+    }
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Liveness {
+    pub alive: bool,
+    /// Status of each task by name
+    pub tasks: BTreeMap<String, TaskStatus>,
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct ComponentStatus {
+    pub ready: bool,
+    pub detail: String,
+}
+
+impl ComponentStatus {
+    fn ready(detail: impl Into<String>) -> Self {
+        Self {
+            ready: true,
+            detail: detail.into(),
+        }
+    }
+
+    fn not_ready(detail: impl Into<String>) -> Self {
+        Self {
+            ready: false,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Readiness {
+    pub ready: bool,
+    pub storage: ComponentStatus,
+    pub monitor: ComponentStatus,
+    pub ingestion: ComponentStatus,
+}
+
+fn status(ok: bool) -> StatusCode {
+    match ok {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    security(()),
+    responses(
+        (status = 200, description = "Every task is running", body = Liveness),
+        (status = 503, description = "A task stopped", body = Liveness),
+    )
+)]
+async fn get_liveness(health: Health) -> Result<impl Reply, Rejection> {
+    let liveness = health.liveness();
+    let status = status(liveness.alive);
+    Ok(warp::reply::with_status(
+        warp::reply::json(&liveness),
+        status,
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    security(()),
+    responses(
+        (status = 200, description = "Ready to serve up to date data", body = Readiness),
+        (status = 503, description = "A component isn't ready", body = Readiness),
+    )
+)]
+async fn get_readiness<S: Storage>(health: Health, storage: S) -> Result<impl Reply, Rejection> {
+    let readiness = health.readiness(&storage).await;
+    let status = status(readiness.ready);
+    Ok(warp::reply::with_status(
+        warp::reply::json(&readiness),
+        status,
+    ))
+}
+
+/// - /healthz - liveness, see [Health::liveness]
+/// - /readyz - readiness, see [Health::readiness]
+pub fn routes<S>(
+    storage: S,
+    health: Health,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    let health_move = health.clone();
+    let liveness_route = warp::path!("healthz")
+        .and(warp::get())
+        .and(warp::any().map(move || health_move.clone()))
+        .and_then(get_liveness);
+
+    let readiness_route = warp::path!("readyz")
+        .and(warp::get())
+        .and(warp::any().map(move || health.clone()))
+        .and(warp::any().map(move || storage.clone()))
+        .and_then(get_readiness);
+
+    liveness_route.or(readiness_route)
+}
+
+#[cfg(test)]
+mod health_tests {
+    use crate::api;
+    use crate::auth::ApiKeys;
+    use crate::health::*;
+    use crate::storage::InMemoryStorage;
+
+    #[tokio::test]
+    async fn liveness() {
+        let health = Health::default();
+        let routes = api::TestRoutes::default()
+            .keys(ApiKeys::new([]))
+            .health(health.clone())
+            .build();
+
+        // served without an API key
+        let response = warp::test::request().path("/healthz").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let monitor = health.task("monitor");
+        let aggregator = health.task("aggregator");
+        drop(monitor);
+        let response = warp::test::request().path("/healthz").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["alive"], false);
+        assert_eq!(body["tasks"]["monitor"], "stopped");
+        assert_eq!(body["tasks"]["aggregator"], "running");
+        drop(aggregator);
+    }
+
+    #[tokio::test]
+    async fn readiness() {
+        let health = Health::new(Metrics::new(), 10);
+        let metrics = health.metrics().clone();
+        let routes = api::TestRoutes::default()
+            .keys(ApiKeys::new([]))
+            .health(health)
+            .build();
+        let readiness = || async {
+            let response = warp::test::request().path("/readyz").reply(&routes).await;
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            (response.status(), body)
+        };
+
+        let (status, body) = readiness().await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["ready"], false);
+        assert_eq!(body["storage"]["ready"], true);
+        assert_eq!(body["monitor"]["ready"], false);
+        assert_eq!(body["ingestion"]["ready"], false);
+
+        metrics.monitor_subscribed.set(1);
+        metrics.latest_notified_slot.set(120);
+        metrics.last_stored_slot.set(100);
+        let (status, body) = readiness().await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["monitor"]["ready"], true);
+        assert_eq!(body["ingestion"]["ready"], false);
+        assert_eq!(
+            body["ingestion"]["detail"],
+            "20 slots behind, over the 10 allowed"
+        );
+
+        metrics.last_stored_slot.set(115);
+        let (status, body) = readiness().await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["ready"], true);
+        assert_eq!(body["ingestion"]["detail"], "5 slots behind");
+
+        // serving only, nothing is ingested
+        let health = Health::default().without_ingestion();
+        let readiness = health.readiness(&InMemoryStorage::new()).await;
+        assert!(readiness.ready);
+        assert_eq!(readiness.monitor.detail, "not ingesting");
+    }
+}
@@ -1,8 +1,21 @@
 pub mod aggregator;
 pub mod api;
+pub mod auth;
+pub mod config;
 pub mod error;
+pub mod feed;
+pub mod graphql;
+pub mod health;
+pub mod metrics;
 pub mod monitor;
+pub mod openapi;
+pub mod queue;
+pub mod ratelimit;
 pub mod storage;
 pub mod streamer;
+pub mod supervisor;
+pub mod telemetry;
 pub mod traits;
 pub mod types;
+pub mod verify;
+pub mod webhooks;
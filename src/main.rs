@@ -1,89 +1,526 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use serde::Serialize;
 use solana_client::rpc_config::RpcBlockConfig;
-use solana_data_aggregator::{aggregator, error::Result, monitor, storage, streamer};
+use solana_data_aggregator::{
+    aggregator, api,
+    auth::ApiKeys,
+    config::{self, Config, Overrides, RedactedUrl, StorageBackend},
+    error::{Error, Result},
+    feed,
+    health::Health,
+    metrics::Metrics,
+    monitor,
+    queue::{slot_queue, OverflowPolicy, SlotReceiver},
+    ratelimit::{Limit, RateLimiter, RateLimits},
+    storage, streamer,
+    supervisor::{RestartPolicy, Supervisor},
+    telemetry,
+    traits::Storage,
+    types::{Address, At, Cursor, Order, TransactionQuery},
+    verify::verify,
+    webhooks::Webhooks,
+};
+use solana_program::clock::Slot;
 use solana_transaction_status::UiTransactionEncoding;
-use std::net::SocketAddr;
-use tokio::{signal::ctrl_c, sync::mpsc};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::ctrl_c;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
 
+/// How often replicas reload the keys and watches managed by the process writing storage
+const RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
 /// Solana Data Aggregator CLI
-/// Should use .env file instead of command line arguments
-/// As API Keys and other sensitive information should not be exposed
+/// Settings are layered from a TOML config file, `AGGREGATOR_*` environment variables, then arguments
+/// Prefer the config file or environment for provider URLs, as they embed API keys
 #[derive(Parser)]
 #[command(version, about, long_about = "Solana Data Aggregator")]
 struct Args {
-    /// Socket for our REST API
-    #[arg(short, long, default_value = "127.0.0.1:8080")]
-    socket: SocketAddr,
+    #[command(flatten)]
+    overrides: Overrides,
 
-    /// RPC provider URL
-    #[arg(short, long, default_value = None)]
-    rpc_provider: String,
+    /// Ingests new blocks and serves the API if not given
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-    /// WSS Provider URL
-    #[arg(short, long, default_value = None)]
-    wss_provider: String,
+#[derive(Subcommand)]
+enum Command {
+    /// Ingest new blocks and serve the API
+    Run,
+    /// Serve the API over existing storage, without ingesting
+    Serve,
+    /// Ingest new blocks, without serving the API
+    Ingest,
+    /// Ingest the blocks of a slot range, then exit
+    Backfill {
+        /// First slot of the range
+        #[arg(long)]
+        from: Slot,
+        /// Last slot of the range, included
+        #[arg(long)]
+        to: Slot,
+    },
+    /// Print stored data as JSON, without a server
+    #[command(subcommand)]
+    Query(Query),
+    /// Check the integrity of storage, failing if any issue is found
+    Verify,
+}
 
-    /// Path for our JSON DB file e.g. /tmp/solana_data_aggregator.json
-    #[arg(short, long, default_value = None)]
-    db_path: String,
+#[derive(Subcommand)]
+enum Query {
+    /// Balance of an account
+    Account {
+        address: Address,
+        /// Point in history to look the account up at, `slot:<slot>` or `time:<unix seconds>`
+        #[arg(long)]
+        at: Option<At>,
+    },
+    /// Transactions made by an account
+    Transactions {
+        address: Address,
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+        #[arg(long, value_enum, default_value_t = Order::default())]
+        order: Order,
+        /// `next_cursor` of the previous page
+        #[arg(long)]
+        cursor: Option<Cursor>,
+    },
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // start logger
-    env_logger::init();
+/// Parts of the aggregator run by a process
+#[derive(Clone, Copy)]
+struct Roles {
+    ingest: bool,
+    serve: bool,
+}
 
-    let token = CancellationToken::new();
-    let args = Args::parse();
+fn main() -> Result<()> {
+    let vars = std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)));
+    let args: Args = config::parse_args(std::env::args_os(), vars);
+    let config = Config::load(args.overrides)?;
+    let command = args.command.unwrap_or(Command::Run);
+    if config.storage.read_only
+        && matches!(
+            command,
+            Command::Run | Command::Ingest | Command::Backfill { .. }
+        )
+    {
+        return Err(Error::VarError(String::from(
+            "Blocks can't be ingested into read-only storage, only serve, query and verify can use it",
+        )));
+    }
 
-    // create and start slot monitor
-    let (monitor_tx, monitor_rx) = mpsc::unbounded_channel();
-    let monitor_token = token.clone();
-    let monitor = monitor::SlotMonitor::new(&args.wss_provider, monitor_token, monitor_tx).await?;
-    let monitor_fut = tokio::spawn(async move { monitor.start_monitoring().await });
-    log::debug!("Slot monitor started");
+    // start tracing, the guard flushes exported spans once the runtime is shut down
+    // commands printing results keep stdout to themselves
+    let _telemetry = match command {
+        Command::Query(_) | Command::Verify => telemetry::init(
+            config.telemetry.log_format,
+            config
+                .telemetry
+                .otlp_endpoint
+                .as_ref()
+                .map(RedactedUrl::expose),
+            std::io::stderr,
+        )?,
+        _ => telemetry::init(
+            config.telemetry.log_format,
+            config
+                .telemetry
+                .otlp_endpoint
+                .as_ref()
+                .map(RedactedUrl::expose),
+            std::io::stdout,
+        )?,
+    };
+    tracing::info!(?config, "Configuration loaded");
 
-    // create storage instance
-    let storage = storage::Database::new(&args.db_path)?;
-    log::debug!("Storage initialized");
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| Error::JoinError(e.to_string()))?;
+    // serve never writes blocks, a writable database would be loaded once and go stale,
+    // while admin writes would overwrite the file with that stale copy
+    let read_only = config.storage.read_only || matches!(command, Command::Serve);
+    runtime.block_on(async {
+        // create storage instance
+        match config.storage.backend {
+            // blocks are committed by another process
+            StorageBackend::Json if read_only => {
+                let db_path = config.storage.path.clone().unwrap_or_default();
+                let storage = storage::Replica::open(&db_path)?;
+                execute(command, config, storage).await
+            }
+            StorageBackend::Json => {
+                let db_path = config.storage.path.clone().unwrap_or_default();
+                let storage = storage::Database::new(&db_path)?;
+                execute(command, config, storage).await
+            }
+            StorageBackend::Memory => {
+                execute(command, config, storage::InMemoryStorage::new()).await
+            }
+        }
+    })
+}
+
+async fn execute<S>(command: Command, config: Config, storage: S) -> Result<()>
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    tracing::debug!("Storage initialized");
+    match command {
+        Command::Run => {
+            let roles = Roles {
+                ingest: true,
+                serve: true,
+            };
+            run(config, storage, roles).await
+        }
+        Command::Serve => {
+            let roles = Roles {
+                ingest: false,
+                serve: true,
+            };
+            run(config, storage, roles).await
+        }
+        Command::Ingest => {
+            let roles = Roles {
+                ingest: true,
+                serve: false,
+            };
+            run(config, storage, roles).await
+        }
+        Command::Backfill { from, to } => backfill(config, storage, from, to).await,
+        Command::Query(Query::Account { address, at }) => {
+            let account = match at {
+                Some(at) => storage.get_account_at(&address, at).await?,
+                None => storage.get_account(&address).await?,
+            };
+            print(&account)
+        }
+        Command::Query(Query::Transactions {
+            address,
+            limit,
+            order,
+            cursor,
+        }) => {
+            let query = TransactionQuery {
+                limit,
+                order,
+                cursor,
+                ..TransactionQuery::default()
+            };
+            print(&storage.get_transactions(&address, &query).await?)
+        }
+        Command::Verify => {
+            let report = verify(&storage).await?;
+            print(&report)?;
+            match report.issues.len() {
+                0 => Ok(()),
+                issues => Err(Error::Inconsistent(format!(
+                    "{} integrity issues found",
+                    issues
+                ))),
+            }
+        }
+    }
+}
 
-    // create streamer
-    let block_config = RpcBlockConfig {
+/// Prints `value` to stdout as JSON
+fn print<T: Serialize>(value: &T) -> Result<()> {
+    let json =
+        serde_json::to_string_pretty(value).map_err(|e| Error::InvalidInput(e.to_string()))?;
+    println!("{}", json);
+    Ok(())
+}
+
+fn block_config(config: &Config) -> RpcBlockConfig {
+    RpcBlockConfig {
         max_supported_transaction_version: Some(0),
         encoding: Some(UiTransactionEncoding::JsonParsed),
+        commitment: Some(config.ingestion.commitment.into()),
         ..RpcBlockConfig::default()
+    }
+}
+
+async fn streamer(
+    config: &Config,
+    token: CancellationToken,
+    slots: SlotReceiver,
+    metrics: Metrics,
+) -> Result<streamer::Streamer> {
+    let streamer = streamer::Streamer::new(
+        config.rpc_provider()?,
+        token,
+        slots,
+        block_config(config),
+        config.ingestion.concurrency,
+        config.filters.clone(),
+        metrics,
+    )
+    .await?;
+    tracing::debug!("Streamer initialized");
+    Ok(streamer)
+}
+
+async fn run<S>(config: Config, storage: S, roles: Roles) -> Result<()>
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    let metrics = Metrics::new();
+    // ingestion resumes from the last stored block
+    if let Ok(block) = storage.get_latest_block().await {
+        metrics.last_stored_slot.set(block.slot as i64);
+    }
+    let health = Health::new(metrics.clone(), config.ingestion.max_ingestion_lag);
+    let health = match roles.ingest {
+        true => health,
+        false => health.without_ingestion(),
     };
-    let streamer_token = token.clone();
-    let streamer =
-        streamer::Streamer::new(&args.rpc_provider, streamer_token, monitor_rx, block_config)
-            .await?;
-    log::debug!("Streamer initialized");
-
-    // start aggregator
-    let aggregator_token = token.clone();
-    let storage_clone = storage.clone();
-    let mut aggregator = aggregator::Aggregator::new(streamer, aggregator_token, storage_clone);
-    let aggregator_fut = tokio::spawn(async move { aggregator.run().await });
-    log::debug!("Aggregator started");
-
-    // start api
-    let api_token = token.clone();
-    let api_fut = tokio::spawn(solana_data_aggregator::api::run_api(
-        args.socket,
-        storage,
-        api_token,
-    ));
-    log::debug!("API started");
+    let token = CancellationToken::new();
+
+    let mut supervisor = Supervisor::new(
+        health.clone(),
+        token.clone(),
+        RestartPolicy {
+            max_restarts: config.ingestion.max_restarts,
+            ..RestartPolicy::default()
+        },
+    );
+    let feed = feed::block_feed();
+    let webhooks = Webhooks::load(&config.webhooks, &storage).await?;
+    if roles.ingest && !webhooks.is_enabled() && !webhooks.watchlist().is_empty() {
+        tracing::warn!("Addresses are watched but no webhook URL is configured");
+    }
+
+    if roles.ingest {
+        // start slot monitor, reconnecting on every restart
+        let (monitor_tx, monitor_rx) = slot_queue(
+            config.ingestion.queue_capacity,
+            config.ingestion.overflow,
+            metrics.clone(),
+        );
+        let monitor_token = token.clone();
+        let monitor_metrics = metrics.clone();
+        let wss_provider = config.wss_provider()?.to_string();
+        supervisor.spawn("monitor", move || {
+            let wss_provider = wss_provider.clone();
+            let token = monitor_token.clone();
+            let monitor_tx = monitor_tx.clone();
+            let metrics = monitor_metrics.clone();
+            async move {
+                monitor::SlotMonitor::new(&wss_provider, token, monitor_tx, metrics)
+                    .await?
+                    .start_monitoring()
+                    .await
+            }
+        });
+        tracing::debug!("Slot monitor started");
+
+        // start aggregator, restarts resume with the same streamer
+        let streamer = streamer(&config, token.clone(), monitor_rx, metrics.clone()).await?;
+        let aggregator = Arc::new(Mutex::new(aggregator::Aggregator::new(
+            streamer,
+            token.clone(),
+            storage.clone(),
+            feed.clone(),
+            metrics.clone(),
+            webhooks.clone(),
+        )));
+        supervisor.spawn("aggregator", move || {
+            let aggregator = aggregator.clone();
+            async move { aggregator.lock().await.run().await }
+        });
+        tracing::debug!("Aggregator started");
+    }
+
+    if roles.serve {
+        let keys = ApiKeys::load(config.api.api_keys.as_deref(), &storage).await?;
+        if !keys.is_enabled() {
+            tracing::warn!("No API keys configured, the API is open to anyone");
+        }
+        let limiter = RateLimiter::new(RateLimits {
+            per_ip: config.api.ip_rate_limit.map(Limit::per_minute),
+            per_key: config.api.key_rate_limit.map(Limit::per_minute),
+        });
+
+        // replicas can't change keys and watches, they follow those of the writer
+        if storage.is_read_only() {
+            let state = (
+                config.clone(),
+                storage.clone(),
+                keys.clone(),
+                webhooks.clone(),
+                token.clone(),
+            );
+            supervisor.spawn("reload", move || {
+                let (config, storage, keys, webhooks, token) = state.clone();
+                async move { reload(&config, &storage, &keys, &webhooks, &token).await }
+            });
+        }
+
+        // start api
+        let api_token = token.clone();
+        let socket = config.api.socket;
+        let routes = api::routes(storage, feed, keys, limiter, health.clone(), webhooks);
+        supervisor.spawn("api", move || {
+            let api = api::run_api(socket, routes.clone(), api_token.clone());
+            async move {
+                api.await;
+                Ok(())
+            }
+        });
+        tracing::debug!("API started");
+    }
 
     // graceful shutdown monitor
     let shutdown_token = token.clone();
-    let shutdown_fut = tokio::spawn(async move {
-        ctrl_c().await.expect("failed to listen for ctrl+c event");
-        log::info!("TERMINATING");
-        shutdown_token.cancel();
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = shutdown_signal() => {
+                tracing::info!("TERMINATING");
+                shutdown_token.cancel();
+            }
+            // the supervisor gave up on a task
+            _ = shutdown_token.cancelled() => {}
+        }
+    });
+
+    // queued slots are drained once cancelled, for no longer than the shutdown timeout
+    let tasks = supervisor.join();
+    tokio::pin!(tasks);
+    tokio::select! {
+        result = &mut tasks => return result,
+        _ = token.cancelled() => {}
+    }
+    let timeout = Duration::from_secs(config.ingestion.shutdown_timeout);
+    match tokio::time::timeout(timeout, tasks).await {
+        Ok(result) => result,
+        Err(_) => {
+            tracing::error!("Shutdown timed out after {:?}, exiting undrained", timeout);
+            Err(Error::JoinError(format!(
+                "shutdown timed out after {:?}",
+                timeout
+            )))
+        }
+    }
+}
+
+/// Reloads keys and watches every [RELOAD_INTERVAL] until cancelled
+/// Failures are logged, the keys and watches loaded last are kept until the next reload
+async fn reload<S: Storage>(
+    config: &Config,
+    storage: &S,
+    keys: &ApiKeys,
+    webhooks: &Webhooks,
+    token: &CancellationToken,
+) -> Result<()> {
+    let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = token.cancelled() => return Ok(()),
+        }
+        if let Err(error) = keys.reload(config.api.api_keys.as_deref(), storage).await {
+            tracing::warn!("Failed to reload API keys: {}", error);
+        }
+        if let Err(error) = webhooks.reload(&config.webhooks, storage).await {
+            tracing::warn!("Failed to reload the watchlist: {}", error);
+        }
+    }
+}
+
+/// Fetches and stores the blocks of the slots from `from` to `to`, skipping those already stored
+async fn backfill<S>(config: Config, storage: S, from: Slot, to: Slot) -> Result<()>
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    if from > to {
+        return Err(Error::InvalidInput(format!(
+            "Slot range {}..={} is empty",
+            from, to
+        )));
+    }
+    let metrics = Metrics::new();
+    let token = CancellationToken::new();
+
+    // every slot of the range is fetched, the queue only holds up queueing
+    let (slot_tx, slot_rx) = slot_queue(
+        config.ingestion.queue_capacity,
+        OverflowPolicy::Block,
+        metrics.clone(),
+    );
+    let streamer = streamer(&config, token.clone(), slot_rx, metrics.clone()).await?;
+    let mut aggregator = aggregator::Aggregator::new(
+        streamer,
+        token.clone(),
+        storage,
+        feed::block_feed(),
+        metrics.clone(),
+        // past transfers aren't worth alerting on
+        Webhooks::disabled(),
+    );
+
+    let producer_token = token.clone();
+    tokio::spawn(async move {
+        for slot in from..=to {
+            tokio::select! {
+                result = slot_tx.send(slot) => result?,
+                _ = producer_token.cancelled() => return Ok(()),
+            }
+        }
+        // every slot is queued, the aggregator stops once they're drained
+        producer_token.cancel();
+        Ok::<(), Error>(())
+    });
+
+    let shutdown_token = token.clone();
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = shutdown_signal() => {
+                tracing::info!("TERMINATING");
+                shutdown_token.cancel();
+            }
+            _ = shutdown_token.cancelled() => {}
+        }
     });
 
-    let _ = tokio::join!(shutdown_fut, monitor_fut, aggregator_fut, api_fut);
+    tracing::info!("Backfilling slots {} to {}", from, to);
+    match aggregator.run().await {
+        Ok(()) | Err(Error::Termination) => {}
+        Err(error) => return Err(error),
+    }
+    tracing::info!(
+        blocks = metrics.blocks_ingested.get(),
+        skipped = metrics.slots_skipped.get(),
+        missing = metrics.slots_missing.get(),
+        "Backfilled slots {} to {}",
+        from,
+        to
+    );
     Ok(())
 }
+
+/// Resolves on ctrl-c, or SIGTERM where there are signals
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        signal(SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        result = ctrl_c() => result.expect("failed to listen for ctrl+c event"),
+        _ = terminate => {}
+    }
+}
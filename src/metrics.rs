@@ -0,0 +1,358 @@
+use crate::openapi::{ApiDoc, OPENAPI_PATH};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::Arc;
+use utoipa::OpenApi;
+use warp::http::header::CONTENT_TYPE;
+use warp::log::{Info, Log};
+use warp::{Filter, Rejection, Reply};
+
+/// Prefix of every exported metric
+const NAMESPACE: &str = "aggregator";
+
+/// Path the metrics are served at
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Served routes missing from the OpenAPI document, `*` matches any remaining segments
+const UNDOCUMENTED_ROUTES: [&str; 6] = [
+    "/graphql",
+    "/graphiql",
+    OPENAPI_PATH,
+    "/docs",
+    "/docs/*",
+    METRICS_PATH,
+];
+
+/// Route label of requests that matched none of the served routes
+/// Keeps the label's cardinality bounded no matter what paths clients send
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Pipeline and API metrics, exported in the Prometheus text format at [METRICS_PATH]
+/// Cheap to clone, clones update the same metrics
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    registry: Registry,
+    /// Blocks recorded into storage
+    pub blocks_ingested: IntCounter,
+    /// Transfers parsed out of the recorded blocks
+    pub transfers_parsed: IntCounter,
+    /// Slots the leader skipped, see [crate::error::Error::SlotSkipped]
+    pub slots_skipped: IntCounter,
+    /// Slots the RPC provider had no block for, see [crate::error::Error::SlotMissing]
+    pub slots_missing: IntCounter,
+    /// Latency of RPC calls in seconds, by RPC method
+    pub rpc_latency: HistogramVec,
+    /// Slots queued by the monitor and not yet taken by the streamer
+    pub monitor_channel_depth: IntGauge,
+    /// Slots the monitor can queue, see [crate::queue::slot_queue]
+    pub slot_queue_capacity: IntGauge,
+    /// Slots dropped from the full queue, to be backfilled
+    pub slots_dropped: IntCounter,
+    /// Slots replaced in the full queue, never to be fetched
+    pub slots_coalesced: IntCounter,
+    /// Dropped slots waiting to be backfilled
+    pub backfill_depth: IntGauge,
+    /// 1 while the monitor's slot subscription is live, 0 otherwise
+    pub monitor_subscribed: IntGauge,
+    /// Latest slot the monitor was notified of
+    pub latest_notified_slot: IntGauge,
+    /// Highest slot of the blocks recorded into storage
+    pub last_stored_slot: IntGauge,
+    /// Slots between the latest notified and the last stored, updated on every export
+    ingestion_lag: IntGauge,
+    api_requests: IntCounterVec,
+    api_request_duration: HistogramVec,
+    /// Templates requests are labelled with, split into segments
+    routes: Arc<Vec<Vec<String>>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some(NAMESPACE.to_string()), None)
+            .expect("metrics namespace is valid");
+
+        let blocks_ingested =
+            IntCounter::new("blocks_ingested_total", "Blocks recorded into storage").unwrap();
+        let transfers_parsed = IntCounter::new(
+            "transfers_parsed_total",
+            "Transfers parsed out of recorded blocks",
+        )
+        .unwrap();
+        let slots_skipped =
+            IntCounter::new("slots_skipped_total", "Slots skipped by the leader").unwrap();
+        let slots_missing = IntCounter::new(
+            "slots_missing_total",
+            "Slots the RPC provider had no block for",
+        )
+        .unwrap();
+        let rpc_latency = HistogramVec::new(
+            HistogramOpts::new("rpc_latency_seconds", "Latency of RPC calls"),
+            &["method"],
+        )
+        .unwrap();
+        let monitor_channel_depth = IntGauge::new(
+            "monitor_channel_depth",
+            "Slots queued by the monitor, waiting for the streamer",
+        )
+        .unwrap();
+        let slot_queue_capacity = IntGauge::new(
+            "slot_queue_capacity",
+            "Slots the monitor can queue for the streamer",
+        )
+        .unwrap();
+        let slots_dropped = IntCounter::new(
+            "slots_dropped_total",
+            "Slots dropped from the full queue, to be backfilled",
+        )
+        .unwrap();
+        let slots_coalesced = IntCounter::new(
+            "slots_coalesced_total",
+            "Slots replaced in the full queue, never to be fetched",
+        )
+        .unwrap();
+        let backfill_depth =
+            IntGauge::new("backfill_depth", "Dropped slots waiting to be backfilled").unwrap();
+        let monitor_subscribed = IntGauge::new(
+            "monitor_subscribed",
+            "Whether the monitor's slot subscription is live",
+        )
+        .unwrap();
+        let latest_notified_slot = IntGauge::new(
+            "latest_notified_slot",
+            "Latest slot the monitor was notified of",
+        )
+        .unwrap();
+        let last_stored_slot = IntGauge::new(
+            "last_stored_slot",
+            "Slot of the latest block recorded into storage",
+        )
+        .unwrap();
+        let ingestion_lag = IntGauge::new(
+            "ingestion_lag_slots",
+            "Latest notified slot minus last stored slot",
+        )
+        .unwrap();
+        let api_requests = IntCounterVec::new(
+            Opts::new("api_requests_total", "API requests served"),
+            &["route", "method", "status"],
+        )
+        .unwrap();
+        let api_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "api_request_duration_seconds",
+                "Time taken to answer API requests",
+            ),
+            &["route", "method"],
+        )
+        .unwrap();
+
+        // names are fixed and distinct, registering can't fail
+        registry
+            .register(Box::new(blocks_ingested.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(transfers_parsed.clone()))
+            .unwrap();
+        registry.register(Box::new(slots_skipped.clone())).unwrap();
+        registry.register(Box::new(slots_missing.clone())).unwrap();
+        registry.register(Box::new(rpc_latency.clone())).unwrap();
+        registry
+            .register(Box::new(monitor_channel_depth.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(slot_queue_capacity.clone()))
+            .unwrap();
+        registry.register(Box::new(slots_dropped.clone())).unwrap();
+        registry
+            .register(Box::new(slots_coalesced.clone()))
+            .unwrap();
+        registry.register(Box::new(backfill_depth.clone())).unwrap();
+        registry
+            .register(Box::new(monitor_subscribed.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(latest_notified_slot.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(last_stored_slot.clone()))
+            .unwrap();
+        registry.register(Box::new(ingestion_lag.clone())).unwrap();
+        registry.register(Box::new(api_requests.clone())).unwrap();
+        registry
+            .register(Box::new(api_request_duration.clone()))
+            .unwrap();
+
+        // literal segments take precedence, `/blocks/latest` isn't a `/blocks/{height}`
+        let mut routes: Vec<Vec<String>> = ApiDoc::openapi()
+            .paths
+            .paths
+            .into_keys()
+            .chain(UNDOCUMENTED_ROUTES.iter().map(|route| route.to_string()))
+            .map(|route| route.split('/').map(String::from).collect())
+            .collect();
+        routes.sort_by_key(|segments: &Vec<String>| {
+            segments
+                .iter()
+                .filter(|segment| segment.starts_with('{') || *segment == "*")
+                .count()
+        });
+
+        Self {
+            registry,
+            blocks_ingested,
+            transfers_parsed,
+            slots_skipped,
+            slots_missing,
+            rpc_latency,
+            monitor_channel_depth,
+            slot_queue_capacity,
+            slots_dropped,
+            slots_coalesced,
+            backfill_depth,
+            monitor_subscribed,
+            latest_notified_slot,
+            last_stored_slot,
+            ingestion_lag,
+            api_requests,
+            api_request_duration,
+            routes: Arc::new(routes),
+        }
+    }
+
+    /// Slots between the latest notified and the last stored
+    /// `None` until the monitor is notified of a slot and a block is stored
+    pub fn ingestion_lag(&self) -> Option<u64> {
+        let notified = self.latest_notified_slot.get();
+        let stored = self.last_stored_slot.get();
+        if notified == 0 || stored == 0 {
+            return None;
+        }
+        Some(notified.saturating_sub(stored).max(0) as u64)
+    }
+
+    /// Route template the path was served by, e.g. `/blocks/{height}` for `/blocks/12`
+    fn route(&self, path: &str) -> String {
+        let segments: Vec<&str> = path.split('/').collect();
+        self.routes
+            .iter()
+            .find(|route| {
+                if route.last().is_some_and(|segment| segment == "*") {
+                    let prefix = &route[..route.len() - 1];
+                    return segments.len() >= route.len() && matches(prefix, &segments);
+                }
+                segments.len() == route.len() && matches(route, &segments)
+            })
+            .map(|route| route.join("/"))
+            .unwrap_or_else(|| UNMATCHED_ROUTE.to_string())
+    }
+
+    /// Records a served API request
+    fn observe_request(&self, info: Info) {
+        let route = self.route(info.path());
+        let method = info.method().as_str();
+        let status = info.status().as_u16().to_string();
+        self.api_requests
+            .with_label_values(&[&route, method, &status])
+            .inc();
+        self.api_request_duration
+            .with_label_values(&[&route, method])
+            .observe(info.elapsed().as_secs_f64());
+    }
+
+    /// All metrics in the Prometheus text format
+    pub fn encode(&self) -> String {
+        self.ingestion_lag
+            .set(self.ingestion_lag().unwrap_or_default() as i64);
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("metrics are valid");
+        String::from_utf8(buffer).expect("text format is UTF-8")
+    }
+}
+
+/// Whether the path's segments match the route's, `{..}` segments match any
+fn matches(route: &[String], segments: &[&str]) -> bool {
+    route
+        .iter()
+        .zip(segments)
+        .all(|(route, segment)| route == segment || (route.starts_with('{') && !segment.is_empty()))
+}
+
+/// Counts and times every request passing through, by route, method and status
+pub fn track(metrics: Metrics) -> Log<impl Fn(Info) + Clone> {
+    warp::log::custom(move |info| metrics.observe_request(info))
+}
+
+/// - /metrics - all metrics in the Prometheus text format
+pub fn routes(metrics: Metrics) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("metrics").and(warp::get()).map(move || {
+        warp::reply::with_header(
+            metrics.encode(),
+            CONTENT_TYPE,
+            TextEncoder::new().format_type(),
+        )
+    })
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use crate::api;
+    use crate::health::{Health, DEFAULT_MAX_INGESTION_LAG};
+    use crate::metrics::*;
+    use warp::http::StatusCode;
+
+    #[test]
+    fn route_labels() {
+        let metrics = Metrics::new();
+        assert_eq!(metrics.route("/blocks/12"), "/blocks/{height}");
+        assert_eq!(metrics.route("/blocks/latest"), "/blocks/latest");
+        assert_eq!(metrics.route("/admin/keys/key"), "/admin/keys/{key}");
+        assert_eq!(metrics.route("/transactions"), "/transactions");
+        assert_eq!(metrics.route("/docs/swagger-ui.css"), "/docs/*");
+        assert_eq!(metrics.route("/blocks/"), UNMATCHED_ROUTE);
+        assert_eq!(metrics.route("/missing/path"), UNMATCHED_ROUTE);
+    }
+
+    #[tokio::test]
+    async fn serve_metrics() {
+        let metrics = Metrics::new();
+        let routes = api::TestRoutes::default()
+            .health(Health::new(metrics.clone(), DEFAULT_MAX_INGESTION_LAG))
+            .build();
+        metrics.latest_notified_slot.set(120);
+        assert_eq!(metrics.ingestion_lag(), None);
+        metrics.last_stored_slot.set(100);
+        metrics.blocks_ingested.inc();
+
+        let response = warp::test::request().path("/blocks/1").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = warp::test::request()
+            .path(METRICS_PATH)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let body = String::from_utf8_lossy(response.body());
+        assert!(body.contains("aggregator_blocks_ingested_total 1"));
+        assert!(body.contains("aggregator_ingestion_lag_slots 20"));
+        assert!(body.contains(
+            r#"aggregator_api_requests_total{method="GET",route="/blocks/{height}",status="404"} 1"#
+        ));
+        assert!(body.contains(
+            r#"aggregator_api_request_duration_seconds_count{method="GET",route="/blocks/{height}"} 1"#
+        ));
+    }
+}
@@ -1,8 +1,8 @@
 use crate::error::*;
+use crate::metrics::Metrics;
+use crate::queue::SlotSender;
 use solana_client::nonblocking::pubsub_client::PubsubClient;
-use solana_program::slot_history::Slot;
 use std::sync::Arc;
-use tokio::sync::mpsc::UnboundedSender;
 use tokio_stream::StreamExt;
 use tokio_util::sync::CancellationToken;
 
@@ -10,30 +10,33 @@ use tokio_util::sync::CancellationToken;
 pub struct SlotMonitor {
     // this doesn't need to be in an Arc
     client: Arc<PubsubClient>,
-    sender: UnboundedSender<Slot>,
+    sender: SlotSender,
     token: CancellationToken,
+    metrics: Metrics,
 }
 
 impl SlotMonitor {
     pub async fn new(
         wss_url: &str,
         token: CancellationToken,
-        monitor_tx: UnboundedSender<Slot>,
+        monitor_tx: SlotSender,
+        metrics: Metrics,
     ) -> Result<Self> {
         let client = Arc::new(match PubsubClient::new(wss_url).await {
             Ok(client) => {
-                log::debug!("PubsubClient created");
+                tracing::debug!("PubsubClient created");
                 client
             }
             Err(e) => {
-                log::error!("SlotMonitor: PubsubClient creation failed: {}", e);
-                return Err(Error::PubSubError(e));
+                tracing::error!("SlotMonitor: PubsubClient creation failed: {}", e);
+                return Err(Error::PubSubError(Box::new(e)));
             }
         });
         Ok(Self {
             client,
             sender: monitor_tx,
             token,
+            metrics,
         })
     }
 
@@ -41,36 +44,49 @@ impl SlotMonitor {
         // create subscription
         let (mut sub, unsub) = match self.client.slot_subscribe().await {
             Ok(sub) => {
-                log::debug!("Slot Subscription created");
+                tracing::debug!("Slot Subscription created");
                 sub
             }
             Err(e) => {
-                log::error!("Slot Subscription failed: {}", e);
-                return Err(Error::PubSubError(e));
+                tracing::error!("Slot Subscription failed: {}", e);
+                return Err(Error::PubSubError(Box::new(e)));
             }
         };
 
-        log::debug!("Starting slot monitoring");
+        tracing::debug!("Starting slot monitoring");
+        self.metrics.monitor_subscribed.set(1);
         loop {
-            if self.token.is_cancelled() {
-                // If cancellation occurs, unsubscribe and return
-                unsub().await;
-                log::info!("TERMINATING");
-                return Err(Error::Termination);
-            }
-
-            if let Some(slot_info) = sub.next().await {
-                // If a slot notification is received, queue the slot for processing
-                match self.sender.send(slot_info.root) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        log::error!("Channel failure: {}", e);
-                        return Err(Error::ChannelFailed(
-                            "SlotMonitor".to_string(),
-                            e.to_string(),
-                        ));
-                    }
+            // stop accepting new slots as soon as cancellation occurs, queued ones are drained
+            let notification = tokio::select! {
+                notification = sub.next() => notification,
+                _ = self.token.cancelled() => {
+                    // unsubscribe and return
+                    self.metrics.monitor_subscribed.set(0);
+                    unsub().await;
+                    tracing::info!("TERMINATING");
+                    return Err(Error::Termination);
                 }
+            };
+
+            let Some(slot_info) = notification else {
+                // the provider closed the subscription, no more slots will be notified
+                self.metrics.monitor_subscribed.set(0);
+                tracing::error!("Slot Subscription closed");
+                return Err(Error::ChannelFailed(
+                    "SlotMonitor".to_string(),
+                    "slot subscription closed".to_string(),
+                ));
+            };
+
+            // queue the slot for processing
+            self.metrics.latest_notified_slot.set(slot_info.root as i64);
+            if let Err(e) = self.sender.send(slot_info.root).await {
+                self.metrics.monitor_subscribed.set(0);
+                tracing::error!("Channel failure: {}", e);
+                return Err(Error::ChannelFailed(
+                    "SlotMonitor".to_string(),
+                    e.to_string(),
+                ));
             }
         }
     }
@@ -79,41 +95,56 @@ impl SlotMonitor {
 #[cfg(test)]
 mod slot_monitor_tests {
     use crate::error::Error;
+    use crate::metrics::Metrics;
     use crate::monitor::SlotMonitor;
+    use crate::queue::{slot_queue, OverflowPolicy};
     use tokio_test::{assert_err, assert_ok};
     use tokio_util::sync::CancellationToken;
 
     #[tokio::test(flavor = "multi_thread")]
     async fn sanity_check() {
-        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let (tx, _rx) = slot_queue(1, OverflowPolicy::Block, Metrics::new());
         let token = CancellationToken::new();
 
         // invalid url
-        let monitor = SlotMonitor::new("amdkasjdkasjh", token.clone(), tx.clone()).await;
+        let monitor =
+            SlotMonitor::new("amdkasjdkasjh", token.clone(), tx.clone(), Metrics::new()).await;
         assert_err!(monitor);
 
         // connection refused
-        let monitor = SlotMonitor::new("ws://localhost:8899", token.clone(), tx.clone()).await;
+        let monitor = SlotMonitor::new(
+            "ws://localhost:8899",
+            token.clone(),
+            tx.clone(),
+            Metrics::new(),
+        )
+        .await;
         assert_err!(monitor);
 
         // successful connection
-        let monitor = SlotMonitor::new("wss://api.testnet.solana.com", token, tx).await;
+        let monitor =
+            SlotMonitor::new("wss://api.testnet.solana.com", token, tx, Metrics::new()).await;
         assert_ok!(monitor);
     }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn functional_test() {
-        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (tx, mut rx) = slot_queue(16, OverflowPolicy::Block, Metrics::new());
         let token = CancellationToken::new();
-        let monitor = SlotMonitor::new("wss://api.testnet.solana.com", token.clone(), tx)
-            .await
-            .unwrap();
+        let monitor = SlotMonitor::new(
+            "wss://api.testnet.solana.com",
+            token.clone(),
+            tx,
+            Metrics::new(),
+        )
+        .await
+        .unwrap();
 
         let monitor_fut = tokio::spawn(async move { monitor.start_monitoring().await });
 
         // slots are being received and sent to the channel
         for _i in 0..5 {
-            let slot = rx.recv().await.unwrap();
+            let slot = rx.recv().await.unwrap().slot;
             println!("Slot: {}", slot);
             assert!(slot > 0);
         }
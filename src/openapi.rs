@@ -0,0 +1,230 @@
+use crate::api::{
+    self, AccountsRequest, AccountsResponse, AddressTransactions, ApiError, BlocksResponse,
+    ErrorBody, TransactionsBatchRequest,
+};
+use crate::auth::{self, IssueKeyRequest, API_KEY_HEADER};
+use crate::feed::Subscription;
+use crate::health::{self, ComponentStatus, Liveness, Readiness, TaskState, TaskStatus};
+use crate::supervisor::{self, StatusResponse};
+use crate::types::{
+    Account, Address, ApiKey, At, BalanceBucket, BlockInfo, BlockSummary, Cursor, Interval, Order,
+    Transaction, TransactionPage, Watch,
+};
+use crate::webhooks::{self, Delivery, DeliveryState, WebhookEvent};
+use std::sync::Arc;
+use utoipa::openapi::security::{ApiKey as ApiKeyScheme, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::Config;
+use warp::filters::path::{FullPath, Tail};
+use warp::http::{Response, Uri};
+use warp::{Filter, Rejection, Reply};
+
+/// Path the OpenAPI document is served at
+pub const OPENAPI_PATH: &str = "/openapi.json";
+
+/// OpenAPI 3 document of the REST routes
+/// Operations are described next to their handlers in [api], add new ones to `paths` here
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Solana Data Aggregator",
+        description = "Accounts, transfers and blocks aggregated from the Solana chain"
+    ),
+    paths(
+        api::get_account,
+        api::get_accounts,
+        api::get_account_history,
+        api::get_transactions,
+        api::get_transactions_batch,
+        api::get_latest_block,
+        api::get_block,
+        api::get_blocks,
+        api::stream_transactions,
+        api::stream_blocks,
+        auth::issue_key,
+        auth::revoke_key,
+        health::get_liveness,
+        health::get_readiness,
+        supervisor::get_status,
+        webhooks::get_watchlist,
+        webhooks::put_watch,
+        webhooks::remove_watch,
+        webhooks::get_deliveries,
+    ),
+    components(schemas(
+        Account,
+        AccountsRequest,
+        AccountsResponse,
+        Address,
+        AddressTransactions,
+        ApiKey,
+        At,
+        BalanceBucket,
+        BlockInfo,
+        BlockSummary,
+        BlocksResponse,
+        ComponentStatus,
+        Cursor,
+        Delivery,
+        DeliveryState,
+        ErrorBody,
+        Interval,
+        IssueKeyRequest,
+        Liveness,
+        Order,
+        Readiness,
+        StatusResponse,
+        Subscription,
+        TaskState,
+        TaskStatus,
+        Transaction,
+        TransactionPage,
+        TransactionsBatchRequest,
+        Watch,
+        WebhookEvent,
+    )),
+    tags(
+        (name = "accounts", description = "Account balances and transaction history"),
+        (name = "blocks", description = "Stored blocks"),
+        (name = "streams", description = "Live updates as blocks are stored"),
+        (name = "admin", description = "API key, watchlist and webhook management, requires an admin key"),
+        (name = "health", description = "Liveness, readiness and state of the aggregator's tasks"),
+    ),
+    modifiers(&ApiKeySecurity),
+    security(("api_key" = []))
+)]
+pub struct ApiDoc;
+
+/// Declares the `api_key` scheme routes are secured with, see [auth::authorize]
+struct ApiKeySecurity;
+
+impl Modify for ApiKeySecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKeyScheme::Header(ApiKeyValue::new(API_KEY_HEADER))),
+        );
+    }
+}
+
+/// Serves a file of the embedded Swagger UI
+async fn serve_docs(
+    full_path: FullPath,
+    tail: Tail,
+    config: Arc<Config<'static>>,
+) -> Result<Box<dyn Reply>, Rejection> {
+    // assets are relative to the UI's index, which needs the trailing slash
+    if full_path.as_str() == "/docs" {
+        return Ok(Box::new(warp::redirect::found(Uri::from_static("/docs/"))));
+    }
+    match utoipa_swagger_ui::serve(tail.as_str(), config) {
+        Ok(Some(file)) => Ok(Box::new(
+            Response::builder()
+                .header("Content-Type", file.content_type)
+                .body(file.bytes.into_owned()),
+        )),
+        Ok(None) => Err(warp::reject::not_found()),
+        Err(error) => Err(warp::reject::custom(ApiError::Internal(error.to_string()))),
+    }
+}
+
+/// - /openapi.json - OpenAPI document of the REST routes, see [ApiDoc]
+/// - /docs - Swagger UI for the document
+pub fn routes() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let doc = Arc::new(ApiDoc::openapi());
+    let openapi_route = warp::path!("openapi.json")
+        .and(warp::get())
+        .map(move || warp::reply::json(doc.as_ref()));
+
+    let config = Arc::new(Config::from(OPENAPI_PATH));
+    let docs_route = warp::path("docs")
+        .and(warp::get())
+        .and(warp::path::full())
+        .and(warp::path::tail())
+        .and(warp::any().map(move || config.clone()))
+        .and_then(serve_docs);
+
+    openapi_route.or(docs_route)
+}
+
+#[cfg(test)]
+mod openapi_tests {
+    use crate::api;
+    use crate::openapi::*;
+    use crate::storage::InMemoryStorage;
+    use crate::traits::Storage;
+    use crate::types::Block;
+    use solana_program::pubkey::Pubkey;
+    use warp::http::StatusCode;
+
+    /// Every documented read operation is served and answers with a documented status
+    #[tokio::test]
+    async fn documented_routes() {
+        let mut storage = InMemoryStorage::new();
+        let address = Address::from(Pubkey::new_unique());
+        let block = Block {
+            height: 1,
+            slot: 1,
+            hash: String::from("block_hash"),
+            timestamp: 100100,
+            transactions: vec![Transaction {
+                source: address,
+                destination: Address::from(Pubkey::new_unique()),
+                amount: 100,
+            }],
+        };
+        storage.add_block(&block).await.unwrap();
+        let routes = api::TestRoutes::with_storage(storage).build();
+
+        let doc = ApiDoc::openapi();
+        assert!(!doc.paths.paths.is_empty());
+        for (path, item) in &doc.paths.paths {
+            // other methods change state, they're covered by their own tests
+            let Some(operation) = &item.get else {
+                continue;
+            };
+            let uri = format!("{}?address={}", path.replace("{height}", "1"), address);
+            // streams are only checked for their headers, their body never ends
+            let response = warp::test::request()
+                .path(&uri)
+                .header("connection", "upgrade")
+                .header("upgrade", "websocket")
+                .header("sec-websocket-version", "13")
+                .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+                .filter(&routes)
+                .await
+                .unwrap()
+                .into_response();
+
+            let status = response.status().as_u16().to_string();
+            assert!(
+                operation.responses.responses.contains_key(&status),
+                "{} answered with undocumented status {}",
+                path,
+                status
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn serve_document() {
+        let routes = api::TestRoutes::default().build();
+
+        let response = warp::test::request()
+            .path(OPENAPI_PATH)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let doc: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert!(doc["openapi"].as_str().unwrap().starts_with("3."));
+        assert!(doc["paths"]["/transactions"]["get"].is_object());
+        assert!(doc["components"]["schemas"]["Address"].is_object());
+
+        let response = warp::test::request().path("/docs").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        let response = warp::test::request().path("/docs/").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(String::from_utf8_lossy(response.body()).contains("swagger"));
+    }
+}
@@ -0,0 +1,322 @@
+use crate::error::*;
+use crate::metrics::Metrics;
+use crate::telemetry;
+use clap::ValueEnum;
+use serde::Deserialize;
+use solana_program::clock::Slot;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tracing::Span;
+
+/// Slots queued between the monitor and the streamer by default
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
+/// Dropped slots kept for backfill, older ones are given up on past it
+const MAX_BACKFILL: usize = 100_000;
+
+/// What a full [slot_queue] does with a new slot
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverflowPolicy {
+    /// Wait for the streamer to take a slot, holding up the monitor
+    Block,
+    /// Drop the oldest queued slot, it's fetched later once the queue is empty
+    #[default]
+    DropOldest,
+    /// Replace the newest queued slot, the slots in between are never fetched
+    Coalesce,
+}
+
+/// A notified slot along with it's span, see [telemetry::slot_span]
+#[derive(Debug)]
+pub struct QueuedSlot {
+    pub slot: Slot,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+struct State {
+    slots: VecDeque<QueuedSlot>,
+    /// Slots dropped from the queue, waiting to be fetched
+    backfill: VecDeque<QueuedSlot>,
+    senders: usize,
+    receiver_dropped: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    metrics: Metrics,
+    /// Wakes the receiver once a slot is queued or the last sender is dropped
+    queued: Notify,
+    /// Wakes a blocked sender once a slot is taken
+    taken: Notify,
+}
+
+impl Shared {
+    fn report(&self, state: &State) {
+        self.metrics
+            .monitor_channel_depth
+            .set(state.slots.len() as i64);
+        self.metrics.backfill_depth.set(state.backfill.len() as i64);
+    }
+}
+
+/// Bounded queue of slot notifications from the monitor to the streamer
+/// Once full, new slots are handled according to it's [OverflowPolicy]
+pub fn slot_queue(
+    capacity: usize,
+    policy: OverflowPolicy,
+    metrics: Metrics,
+) -> (SlotSender, SlotReceiver) {
+    let capacity = capacity.max(1);
+    metrics.slot_queue_capacity.set(capacity as i64);
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            slots: VecDeque::with_capacity(capacity),
+            backfill: VecDeque::new(),
+            senders: 1,
+            receiver_dropped: false,
+        }),
+        capacity,
+        policy,
+        metrics,
+        queued: Notify::new(),
+        taken: Notify::new(),
+    });
+    (
+        SlotSender {
+            shared: shared.clone(),
+        },
+        SlotReceiver { shared },
+    )
+}
+
+/// Queues slots, see [slot_queue]
+#[derive(Debug)]
+pub struct SlotSender {
+    shared: Arc<Shared>,
+}
+
+impl Clone for SlotSender {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for SlotSender {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.senders -= 1;
+        }
+        self.shared.queued.notify_one();
+    }
+}
+
+impl SlotSender {
+    /// Queues a slot, waits for room only with [OverflowPolicy::Block]
+    /// Fails once the receiver is dropped
+    pub async fn send(&self, slot: Slot) -> Result<()> {
+        let span = telemetry::slot_span(slot);
+        span.in_scope(|| tracing::debug!("Slot notified"));
+        let mut slot = Some(QueuedSlot { slot, span });
+        loop {
+            // registered before checking for room, so a slot taken meanwhile isn't missed
+            let taken = self.shared.taken.notified();
+            tokio::pin!(taken);
+            taken.as_mut().enable();
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                if state.receiver_dropped {
+                    return Err(Error::ChannelFailed(
+                        "SlotQueue".to_string(),
+                        "receiver dropped".to_string(),
+                    ));
+                }
+
+                let full = state.slots.len() >= self.shared.capacity;
+                match self.shared.policy {
+                    _ if !full => state.slots.extend(slot.take()),
+                    OverflowPolicy::Block => {}
+                    OverflowPolicy::DropOldest => {
+                        if let Some(dropped) = state.slots.pop_front() {
+                            self.shared.metrics.slots_dropped.inc();
+                            if state.backfill.len() >= MAX_BACKFILL {
+                                let lost = state.backfill.pop_front().map(|lost| lost.slot);
+                                tracing::warn!("Backfill full, slot {:?} won't be fetched", lost);
+                            }
+                            state.backfill.push_back(dropped);
+                        }
+                        state.slots.extend(slot.take());
+                    }
+                    OverflowPolicy::Coalesce => {
+                        self.shared.metrics.slots_coalesced.inc();
+                        if let (Some(newest), Some(slot)) = (state.slots.back_mut(), slot.take()) {
+                            *newest = slot;
+                        }
+                    }
+                }
+
+                if !full || self.shared.policy != OverflowPolicy::Block {
+                    self.shared.report(&state);
+                    drop(state);
+                    self.shared.queued.notify_one();
+                    return Ok(());
+                }
+            }
+            taken.await;
+        }
+    }
+}
+
+/// Takes queued slots, see [slot_queue]
+#[derive(Debug)]
+pub struct SlotReceiver {
+    shared: Arc<Shared>,
+}
+
+impl Drop for SlotReceiver {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.receiver_dropped = true;
+        }
+        // blocked senders find out the receiver is gone
+        self.shared.taken.notify_waiters();
+    }
+}
+
+impl SlotReceiver {
+    /// Takes the oldest queued slot, or one dropped for backfill once none are queued
+    /// Waits for one to be queued, returns `None` once every sender is dropped
+    pub async fn recv(&mut self) -> Option<QueuedSlot> {
+        loop {
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                let slot = match state.slots.pop_front() {
+                    Some(slot) => {
+                        self.shared.taken.notify_one();
+                        Some(slot)
+                    }
+                    None => state.backfill.pop_front(),
+                };
+                if slot.is_some() {
+                    self.shared.report(&state);
+                    return slot;
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            self.shared.queued.notified().await;
+        }
+    }
+
+    /// Takes the oldest queued slot without waiting, slots dropped for backfill aren't taken
+    pub fn try_recv(&mut self) -> Option<QueuedSlot> {
+        let mut state = self.shared.state.lock().unwrap();
+        let slot = state.slots.pop_front()?;
+        self.shared.report(&state);
+        drop(state);
+        self.shared.taken.notify_one();
+        Some(slot)
+    }
+
+    /// Slots queued
+    pub fn len(&self) -> usize {
+        self.shared.state.lock().unwrap().slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Slots dropped from the queue and not fetched yet, oldest first
+    pub fn backfill(&self) -> Vec<Slot> {
+        let state = self.shared.state.lock().unwrap();
+        state.backfill.iter().map(|queued| queued.slot).collect()
+    }
+}
+
+#[cfg(test)]
+mod queue_tests {
+    use crate::queue::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn block() {
+        let metrics = Metrics::new();
+        let (tx, mut rx) = slot_queue(2, OverflowPolicy::Block, metrics.clone());
+        tx.send(1).await.unwrap();
+        tx.send(2).await.unwrap();
+        assert_eq!(metrics.monitor_channel_depth.get(), 2);
+
+        // held up until a slot is taken
+        let sender = tokio::spawn(async move { tx.send(3).await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!sender.is_finished());
+        assert_eq!(rx.recv().await.map(|queued| queued.slot), Some(1));
+        sender.await.unwrap().unwrap();
+
+        assert_eq!(rx.recv().await.map(|queued| queued.slot), Some(2));
+        assert_eq!(rx.recv().await.map(|queued| queued.slot), Some(3));
+        // every sender is gone
+        assert_eq!(rx.recv().await.map(|queued| queued.slot), None);
+        assert_eq!(metrics.monitor_channel_depth.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest() {
+        let metrics = Metrics::new();
+        let (tx, mut rx) = slot_queue(2, OverflowPolicy::DropOldest, metrics.clone());
+        for slot in 1..=5 {
+            tx.send(slot).await.unwrap();
+        }
+        assert_eq!(rx.len(), 2);
+        assert_eq!(rx.backfill(), vec![1, 2, 3]);
+        assert_eq!(metrics.slots_dropped.get(), 3);
+        assert_eq!(metrics.backfill_depth.get(), 3);
+
+        // queued slots come first, draining doesn't backfill
+        assert_eq!(rx.try_recv().map(|queued| queued.slot), Some(4));
+        assert_eq!(rx.recv().await.map(|queued| queued.slot), Some(5));
+        assert_eq!(rx.try_recv().map(|queued| queued.slot), None);
+        assert_eq!(rx.recv().await.map(|queued| queued.slot), Some(1));
+        tx.send(6).await.unwrap();
+        assert_eq!(rx.recv().await.map(|queued| queued.slot), Some(6));
+        assert_eq!(rx.recv().await.map(|queued| queued.slot), Some(2));
+        assert_eq!(rx.recv().await.map(|queued| queued.slot), Some(3));
+        assert_eq!(metrics.backfill_depth.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn coalesce() {
+        let metrics = Metrics::new();
+        let (tx, mut rx) = slot_queue(2, OverflowPolicy::Coalesce, metrics.clone());
+        for slot in 1..=5 {
+            tx.send(slot).await.unwrap();
+        }
+        assert_eq!(metrics.slots_coalesced.get(), 3);
+        assert!(rx.backfill().is_empty());
+        assert_eq!(rx.recv().await.map(|queued| queued.slot), Some(1));
+        assert_eq!(rx.recv().await.map(|queued| queued.slot), Some(5));
+        assert!(rx.is_empty());
+    }
+
+    #[tokio::test]
+    async fn receiver_dropped() {
+        let (tx, rx) = slot_queue(1, OverflowPolicy::Block, Metrics::new());
+        tx.send(1).await.unwrap();
+        let blocked = tx.clone();
+        let sender = tokio::spawn(async move { blocked.send(2).await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(rx);
+        assert!(sender.await.unwrap().is_err());
+        assert!(tx.send(3).await.is_err());
+    }
+}
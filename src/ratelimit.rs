@@ -0,0 +1,298 @@
+use crate::auth::{ApiKeys, API_KEY_HEADER};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::http::header::{HeaderValue, RETRY_AFTER};
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+
+/// Clients tracked before windows that already ended are dropped
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Requests a client can make per period
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limit {
+    pub requests: u64,
+    pub period: Duration,
+}
+
+impl Limit {
+    pub fn per_minute(requests: u64) -> Self {
+        Self {
+            requests,
+            period: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Limits applied to every request, a request has to be within all that apply to it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    /// Per remote IP address
+    pub per_ip: Option<Limit>,
+    /// Per API key, for requests carrying an accepted one
+    pub per_key: Option<Limit>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Client {
+    Ip(IpAddr),
+    Key(String),
+}
+
+/// Requests made by a client in the current window
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    used: u64,
+}
+
+/// Standing of a client against the most constraining limit, sent as `RateLimit-*` headers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    pub limit: u64,
+    pub remaining: u64,
+    /// Seconds until the window resets
+    pub reset: u64,
+}
+
+impl RateLimitStatus {
+    fn apply(&self, response: &mut Response) {
+        let headers = response.headers_mut();
+        headers.insert("ratelimit-limit", HeaderValue::from(self.limit));
+        headers.insert("ratelimit-remaining", HeaderValue::from(self.remaining));
+        headers.insert("ratelimit-reset", HeaderValue::from(self.reset));
+    }
+}
+
+/// Rejection of a request over it's limit, answered with 429
+#[derive(Debug)]
+pub struct RateLimited(pub RateLimitStatus);
+
+impl warp::reject::Reject for RateLimited {}
+
+impl RateLimited {
+    /// Adds the `RateLimit-*` and `Retry-After` headers to the error response
+    pub fn apply(&self, response: &mut Response) {
+        self.0.apply(response);
+        let retry_after = HeaderValue::from(self.0.reset);
+        response.headers_mut().insert(RETRY_AFTER, retry_after);
+    }
+}
+
+/// Fixed window rate limiter shared by the API routes
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiter {
+    limits: RateLimits,
+    windows: Arc<Mutex<HashMap<Client, Window>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            windows: Arc::default(),
+        }
+    }
+
+    /// Lets every request through
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Counts a request against every limit that applies to it, unless one of them is exhausted
+    /// Returns the standing against the most constraining limit, `None` if no limit applies
+    fn check(
+        &self,
+        ip: Option<IpAddr>,
+        key: Option<&str>,
+    ) -> Result<Option<RateLimitStatus>, RateLimited> {
+        let ip = ip
+            .zip(self.limits.per_ip)
+            .map(|(ip, limit)| (Client::Ip(ip), limit));
+        let key = key
+            .zip(self.limits.per_key)
+            .map(|(key, limit)| (Client::Key(key.to_string()), limit));
+        let clients: Vec<(Client, Limit)> = ip.into_iter().chain(key).collect();
+        if clients.is_empty() {
+            return Ok(None);
+        }
+
+        let mut windows = self.windows.lock().unwrap();
+        let now = Instant::now();
+        if windows.len() > PRUNE_THRESHOLD {
+            let longest = [self.limits.per_ip, self.limits.per_key]
+                .into_iter()
+                .flatten()
+                .map(|limit| limit.period)
+                .max()
+                .unwrap_or_default();
+            windows.retain(|_, window| now.duration_since(window.start) < longest);
+        }
+
+        let mut statuses = Vec::with_capacity(clients.len());
+        for (client, limit) in &clients {
+            let window = windows.entry(client.clone()).or_insert(Window {
+                start: now,
+                used: 0,
+            });
+            let mut elapsed = now.duration_since(window.start);
+            if elapsed >= limit.period {
+                window.start = now;
+                window.used = 0;
+                elapsed = Duration::ZERO;
+            }
+            let status = RateLimitStatus {
+                limit: limit.requests,
+                remaining: limit.requests.saturating_sub(window.used),
+                reset: (limit.period - elapsed).as_secs().max(1),
+            };
+            if status.remaining == 0 {
+                return Err(RateLimited(status));
+            }
+            statuses.push(status);
+        }
+
+        for (client, _) in &clients {
+            if let Some(window) = windows.get_mut(client) {
+                window.used += 1;
+            }
+        }
+        let status = statuses
+            .into_iter()
+            .map(|status| RateLimitStatus {
+                remaining: status.remaining - 1,
+                ..status
+            })
+            .min_by_key(|status| status.remaining);
+        Ok(status)
+    }
+}
+
+/// Counts the request against the client's limits, rejecting it with [RateLimited] once over
+/// Only keys in `keys` get a limit of their own, others would let clients dodge the IP limit
+/// by making keys up, so requests carrying them only count against their address
+/// Extracts the client's standing, to be sent back with [with_headers]
+pub fn limit(
+    limiter: RateLimiter,
+    keys: ApiKeys,
+) -> impl Filter<Extract = (Option<RateLimitStatus>,), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::header::optional::<String>(API_KEY_HEADER))
+        .and_then(move |remote: Option<SocketAddr>, key: Option<String>| {
+            let limiter = limiter.clone();
+            let key = key.filter(|key| keys.contains(key));
+            async move {
+                limiter
+                    .check(remote.map(|remote| remote.ip()), key.as_deref())
+                    .map_err(warp::reject::custom)
+            }
+        })
+}
+
+/// Adds the `RateLimit-*` headers to a reply
+pub fn with_headers(status: Option<RateLimitStatus>, reply: impl Reply) -> Response {
+    let mut response = reply.into_response();
+    if let Some(status) = status {
+        status.apply(&mut response);
+    }
+    response
+}
+
+#[cfg(test)]
+mod ratelimit_tests {
+    use crate::api;
+    use crate::auth::{ApiKeys, API_KEY_HEADER};
+    use crate::ratelimit::*;
+    use crate::types::ApiKey;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn per_ip_and_key() {
+        let limiter = RateLimiter::new(RateLimits {
+            per_ip: Some(Limit::per_minute(3)),
+            per_key: Some(Limit::per_minute(1)),
+        });
+        let key = ApiKey {
+            key: String::from("key"),
+            name: String::from("client"),
+            quota: None,
+            admin: false,
+        };
+        let routes = api::TestRoutes::default()
+            .keys(ApiKeys::new([key]))
+            .limiter(limiter)
+            .build();
+        let first: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let second: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+
+        let response = warp::test::request()
+            .path("/blocks")
+            .remote_addr(first)
+            .header(API_KEY_HEADER, "key")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["ratelimit-limit"], "1");
+        assert_eq!(response.headers()["ratelimit-remaining"], "0");
+
+        // the key is exhausted from any address, without using up the address's own limit
+        let response = warp::test::request()
+            .path("/blocks")
+            .remote_addr(second)
+            .header(API_KEY_HEADER, "key")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["ratelimit-remaining"], "0");
+        assert!(response.headers().contains_key("retry-after"));
+
+        // made up keys get no limit of their own, they count against the address
+        for (made_up, remaining) in ["a", "b", "c"].into_iter().zip(["2", "1", "0"]) {
+            let response = warp::test::request()
+                .path("/blocks")
+                .remote_addr(second)
+                .header(API_KEY_HEADER, made_up)
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(response.headers()["ratelimit-limit"], "3");
+            assert_eq!(response.headers()["ratelimit-remaining"], remaining);
+        }
+        let response = warp::test::request()
+            .path("/blocks")
+            .remote_addr(second)
+            .header(API_KEY_HEADER, "d")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // limited before reaching the handlers, even rejected requests count
+        let response = warp::test::request()
+            .path("/missing")
+            .remote_addr(first)
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()["ratelimit-remaining"], "1");
+    }
+
+    #[test]
+    fn window_reset() {
+        let limiter = RateLimiter::new(RateLimits {
+            per_ip: Some(Limit {
+                requests: 1,
+                period: Duration::from_millis(50),
+            }),
+            per_key: None,
+        });
+        let ip = Some(IpAddr::from([127, 0, 0, 1]));
+        assert!(limiter.check(ip, Some("key")).is_ok());
+        assert!(limiter.check(ip, None).is_err());
+        std::thread::sleep(Duration::from_millis(60));
+        let status = limiter.check(ip, None).unwrap().unwrap();
+        assert_eq!(status.remaining, 0);
+        assert!(RateLimiter::disabled().check(ip, None).unwrap().is_none());
+    }
+}
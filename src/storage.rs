@@ -2,35 +2,350 @@ use crate::error::*;
 use crate::traits::Storage;
 use crate::types::*;
 use nanodb::nanodb::NanoDB;
+use nanodb::trees::tree::Tree;
 use serde::Deserialize;
 use serde::Serialize;
 use solana_program::clock::Slot;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::{Mutex, RwLock};
 
 pub const LATEST_BLOCKHEIGHT_KEY: &str = "latest_bh";
+/// Sorted heights of every stored block
+pub const BLOCK_HEIGHTS_KEY: &str = "block_heights";
+/// API keys issued at runtime, by key
+pub const API_KEYS_KEY: &str = "api_keys";
+/// Addresses watched for webhooks added at runtime, by address
+pub const WATCHES_KEY: &str = "watches";
+/// Upper bound on the number of buckets a balance series query can span
+pub const MAX_SERIES_BUCKETS: i64 = 10_000;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum DbKey {
     TransactionIndex = 1,
     AccountBalance = 2,
     Block = 3,
+    BalanceHistory = 4,
+    BalanceSeries = 5,
 }
 
 pub fn db_key<T: Display>(key_type: DbKey, key: &T) -> String {
     format!("{:?}-{}", key_type, key)
 }
 
+/// Inserts `entry` into an index sorted by [TransactionIndex::cursor], ignoring duplicates
+fn insert_index(index: &mut Vec<TransactionIndex>, entry: TransactionIndex) {
+    let position = index.partition_point(|e| e.cursor() < entry.cursor());
+    if index.get(position) != Some(&entry) {
+        index.insert(position, entry);
+    }
+}
+
+/// Narrows a sorted transaction index down to the entries within the slot and time bounds of `query`
+/// Block time doesn't decrease with slot, so both bounds are found with a binary search
+fn select_range<'a>(
+    index: &'a [TransactionIndex],
+    query: &TransactionQuery,
+) -> &'a [TransactionIndex] {
+    let start = index.partition_point(|e| {
+        query.from_slot.is_some_and(|slot| e.slot < slot)
+            || query.from_time.is_some_and(|time| e.timestamp < time)
+    });
+    let end = index.partition_point(|e| {
+        query.to_slot.is_none_or(|slot| e.slot <= slot)
+            && query.to_time.is_none_or(|time| e.timestamp <= time)
+    });
+    &index[start..end.max(start)]
+}
+
+/// Selects the entries of a sorted transaction index that make up the page described by `query`
+/// Returns the selected entries and the cursor of the following page, if any
+fn select_page<'a>(
+    index: &'a [TransactionIndex],
+    query: &TransactionQuery,
+) -> (Vec<&'a TransactionIndex>, Option<Cursor>) {
+    let index = select_range(index, query);
+    let mut selected: Vec<&TransactionIndex> = match query.order {
+        Order::Asc => {
+            let start = query
+                .cursor
+                .map_or(0, |cursor| index.partition_point(|e| e.cursor() <= cursor));
+            index[start..]
+                .iter()
+                .take(query.limit.saturating_add(1))
+                .collect()
+        }
+        Order::Desc => {
+            let end = query.cursor.map_or(index.len(), |cursor| {
+                index.partition_point(|e| e.cursor() < cursor)
+            });
+            index[..end]
+                .iter()
+                .rev()
+                .take(query.limit.saturating_add(1))
+                .collect()
+        }
+    };
+
+    // one extra entry was taken to find out if there is a next page
+    let next_cursor = if selected.len() > query.limit {
+        selected.truncate(query.limit);
+        selected.last().map(|e| e.cursor())
+    } else {
+        None
+    };
+    (selected, next_cursor)
+}
+
+/// Lamports moved in and out of an account
+#[derive(Debug, Default)]
+struct Flow {
+    inflow: u64,
+    outflow: u64,
+}
+
+impl Flow {
+    fn delta(&self) -> i64 {
+        self.inflow as i64 - self.outflow as i64
+    }
+}
+
+/// Flows of every account touched by `block`
+fn account_flows(block: &Block) -> BTreeMap<Address, Flow> {
+    let mut flows: BTreeMap<Address, Flow> = BTreeMap::new();
+    for transaction in &block.transactions {
+        flows.entry(transaction.source).or_default().outflow += transaction.amount;
+        flows.entry(transaction.destination).or_default().inflow += transaction.amount;
+    }
+    flows
+}
+
+/// Applies a block's balance change to an account's history, kept sorted by slot
+/// Checkpoints of later blocks are shifted too, so blocks may be recorded out of order
+fn record_checkpoint(history: &mut Vec<BalanceCheckpoint>, block: &Block, delta: i64) {
+    let position = history.partition_point(|c| c.slot < block.slot);
+    if history.get(position).is_none_or(|c| c.slot != block.slot) {
+        let balance = position
+            .checked_sub(1)
+            .map_or(0, |previous| history[previous].balance);
+        history.insert(
+            position,
+            BalanceCheckpoint {
+                slot: block.slot,
+                timestamp: block.timestamp,
+                balance,
+            },
+        );
+    }
+    for checkpoint in &mut history[position..] {
+        checkpoint.balance += delta;
+    }
+}
+
+/// Balance recorded by the last checkpoint at or before `at`
+fn balance_at(history: &[BalanceCheckpoint], at: At) -> i64 {
+    let position = history.partition_point(|c| at.includes(c));
+    position
+        .checked_sub(1)
+        .map_or(0, |previous| history[previous].balance)
+}
+
+/// Applies a block's flow to the bucket of a cached balance series, kept sorted by start
+/// Like checkpoints, balances of later buckets are shifted too
+fn record_bucket(series: &mut Vec<BalanceBucket>, interval: Interval, block: &Block, flow: &Flow) {
+    let start = interval.bucket_start(block.timestamp);
+    let position = series.partition_point(|b| b.start < start);
+    if series.get(position).is_none_or(|b| b.start != start) {
+        let balance = position
+            .checked_sub(1)
+            .map_or(0, |previous| series[previous].balance);
+        series.insert(
+            position,
+            BalanceBucket {
+                start,
+                balance,
+                ..Default::default()
+            },
+        );
+    }
+    series[position].inflow += flow.inflow;
+    series[position].outflow += flow.outflow;
+    for bucket in &mut series[position..] {
+        bucket.balance += flow.delta();
+    }
+}
+
+/// Expands a sparse cached series into every bucket within the bounds of `query`
+fn select_series(series: &[BalanceBucket], query: &SeriesQuery) -> Result<Vec<BalanceBucket>> {
+    let (Some(first), Some(last)) = (series.first(), series.last()) else {
+        return Ok(Vec::new());
+    };
+    let interval = query.interval;
+    // widest span a series can cover, bounds left out are clamped to it
+    let max_span = (MAX_SERIES_BUCKETS - 1) * interval.seconds();
+    let (from, to) = match (query.from_time, query.to_time) {
+        (Some(from), Some(to)) => (interval.bucket_start(from), interval.bucket_start(to)),
+        (Some(from), None) => {
+            let from = interval.bucket_start(from);
+            (from, last.start.min(from.saturating_add(max_span)))
+        }
+        (None, to) => {
+            let to = to.map_or(last.start, |time| interval.bucket_start(time));
+            (first.start.max(to.saturating_sub(max_span)), to)
+        }
+    };
+    if to < from {
+        return Ok(Vec::new());
+    }
+    // bounds are client supplied, their span may not fit an i64
+    if (to as i128 - from as i128) / interval.seconds() as i128 >= MAX_SERIES_BUCKETS as i128 {
+        return Err(Error::InvalidInput(format!(
+            "Series spans more than {} buckets",
+            MAX_SERIES_BUCKETS
+        )));
+    }
+
+    let mut position = series.partition_point(|b| b.start < from);
+    let mut balance = position
+        .checked_sub(1)
+        .map_or(0, |previous| series[previous].balance);
+    let mut buckets = Vec::new();
+    for start in (from..=to).step_by(interval.seconds() as usize) {
+        match series.get(position) {
+            Some(bucket) if bucket.start == start => {
+                balance = bucket.balance;
+                buckets.push(bucket.clone());
+                position += 1;
+            }
+            _ => buckets.push(BalanceBucket {
+                start,
+                balance,
+                ..Default::default()
+            }),
+        }
+    }
+    Ok(buckets)
+}
+
+/// Key of an account's cached balance series
+fn series_key(address: &Address, interval: Interval) -> String {
+    db_key(DbKey::BalanceSeries, &format!("{}-{}", interval, address))
+}
+
+/// Selects the heights of a sorted height index that make up the page described by `query`
+fn select_heights(heights: &[u64], query: &BlockQuery) -> (Vec<u64>, Option<u64>) {
+    let start = query
+        .from
+        .map_or(0, |from| heights.partition_point(|h| *h < from));
+    let end = query
+        .to
+        .map_or(heights.len(), |to| heights.partition_point(|h| *h <= to));
+    let mut selected: Vec<u64> = heights[start..end.max(start)]
+        .iter()
+        .take(query.limit.saturating_add(1))
+        .copied()
+        .collect();
+
+    // one extra height was taken to find out if there is a next page
+    let next_height = if selected.len() > query.limit {
+        selected.pop()
+    } else {
+        None
+    };
+    (selected, next_height)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct ChainMedadata {
     pub last_slot: Slot,
     pub last_block_height: u64,
 }
 
+/// Database file, every commit bumps the generation [Replica]s reading the file watch
 #[derive(Debug, Clone)]
-pub struct Database(NanoDB);
+pub struct Database(NanoDB, String);
 impl Database {
     pub fn new(path: &str) -> Result<Self> {
         let db = NanoDB::open(path)?;
-        Ok(Self(db))
+        Ok(Self(db, path.to_string()))
+    }
+
+    /// Writes the file, then bumps its generation so replicas reload it
+    async fn commit(&mut self) -> Result<()> {
+        self.0.write().await?;
+        let path = generation_path(&self.1);
+        let generation = read_generation(&path).await.unwrap_or(0) + 1;
+        // replaced in one go so replicas never read a partial generation
+        let staged = format!("{}.tmp", path);
+        tokio::fs::write(&staged, generation.to_string())
+            .await
+            .map_err(|error| Error::StorageError(error.to_string()))?;
+        tokio::fs::rename(&staged, &path)
+            .await
+            .map_err(|error| Error::StorageError(error.to_string()))?;
+        Ok(())
+    }
+
+    /// Page of `address`'s transactions out of a snapshot, `None` if it made none
+    /// Blocks are decoded once into `blocks`, pages of a batch often share them
+    fn transaction_page(
+        data: &Tree,
+        address: &Address,
+        query: &TransactionQuery,
+        blocks: &mut HashMap<u64, Block>,
+    ) -> Result<Option<TransactionPage>> {
+        let tx_index = match data.get(db_key(DbKey::TransactionIndex, &address).as_ref()) {
+            Ok(tx_index) => tx_index.into::<Vec<TransactionIndex>>().unwrap_or_default(),
+            Err(_) => return Ok(None),
+        };
+
+        let (selected, next_cursor) = select_page(&tx_index, query);
+        let cursors = selected.iter().map(|index| index.cursor()).collect();
+        let mut transactions = Vec::new();
+        for index in selected {
+            let block = match blocks.entry(index.block_height) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let block_key = db_key(DbKey::Block, &index.block_height);
+                    let block = data
+                        .get(&block_key)
+                        .map_err(|_| {
+                            Error::Inconsistent(format!("Block {:?} not found", index.block_height))
+                        })?
+                        .into::<Block>()
+                        .unwrap();
+                    entry.insert(block)
+                }
+            };
+
+            transactions.push(block.transactions[index.index].clone());
+        }
+
+        Ok(Some(TransactionPage {
+            transactions,
+            cursors,
+            next_cursor,
+        }))
+    }
+
+    async fn stored_api_keys(&self) -> BTreeMap<String, ApiKey> {
+        match self.0.data().await.get(API_KEYS_KEY) {
+            Ok(keys) => keys.into::<BTreeMap<String, ApiKey>>().unwrap_or_default(),
+            Err(_) => BTreeMap::new(),
+        }
+    }
+
+    async fn stored_watches(&self) -> BTreeMap<Address, Watch> {
+        match self.0.data().await.get(WATCHES_KEY) {
+            Ok(watches) => watches
+                .into::<BTreeMap<Address, Watch>>()
+                .unwrap_or_default(),
+            Err(_) => BTreeMap::new(),
+        }
     }
 }
 
@@ -38,16 +353,15 @@ impl Storage for Database {
     async fn add_block(&mut self, block: &Block) -> Result<()> {
         let block_key = db_key(DbKey::Block, &block.height);
         if self.0.data().await.get(&block_key).is_ok() {
-            return Err(Error::StorageError(format!(
-                "Block {:?} exists",
-                block.height
-            )));
+            return Err(Error::BlockExists(block.height));
         }
 
         for (index, transaction) in block.transactions.iter().enumerate() {
             // Record transactions done by sender in their transaction index
             let tx_index = TransactionIndex {
                 block_height: block.height,
+                slot: block.slot,
+                timestamp: block.timestamp,
                 index,
             };
 
@@ -63,15 +377,13 @@ impl Storage for Database {
                 Err(_) => Vec::new(),
             };
 
-            if !sender_index.contains(&tx_index) {
-                sender_index.push(tx_index);
-                self.0
-                    .insert(
-                        db_key(DbKey::TransactionIndex, &transaction.source).as_str(),
-                        &sender_index,
-                    )
-                    .await?;
-            }
+            insert_index(&mut sender_index, tx_index);
+            self.0
+                .insert(
+                    db_key(DbKey::TransactionIndex, &transaction.source).as_str(),
+                    &sender_index,
+                )
+                .await?;
 
             let sender_balance_key = db_key(DbKey::AccountBalance, &transaction.source);
             let sender_balance = match self.0.data().await.get(&sender_balance_key) {
@@ -98,45 +410,120 @@ impl Storage for Database {
                 )
                 .await?;
         }
+
+        for (address, flow) in account_flows(block) {
+            let history_key = db_key(DbKey::BalanceHistory, &address);
+            let mut history = match self.0.data().await.get(&history_key) {
+                Ok(history) => history.into::<Vec<BalanceCheckpoint>>().unwrap_or_default(),
+                Err(_) => Vec::new(),
+            };
+            record_checkpoint(&mut history, block, flow.delta());
+            self.0.insert(&history_key, &history).await?;
+
+            for interval in Interval::ALL {
+                let key = series_key(&address, interval);
+                let mut series = match self.0.data().await.get(&key) {
+                    Ok(series) => series.into::<Vec<BalanceBucket>>().unwrap_or_default(),
+                    Err(_) => Vec::new(),
+                };
+                record_bucket(&mut series, interval, block, &flow);
+                self.0.insert(&key, &series).await?;
+            }
+        }
+
+        let mut heights = match self.0.data().await.get(BLOCK_HEIGHTS_KEY) {
+            Ok(heights) => heights.into::<Vec<u64>>().unwrap_or_default(),
+            Err(_) => Vec::new(),
+        };
+        let position = heights.partition_point(|h| *h < block.height);
+        heights.insert(position, block.height);
+        self.0.insert(BLOCK_HEIGHTS_KEY, &heights).await?;
+
+        let latest = match self.0.data().await.get(LATEST_BLOCKHEIGHT_KEY) {
+            Ok(latest) => latest.into::<ChainMedadata>().ok(),
+            Err(_) => None,
+        };
+        if latest.is_none_or(|latest| latest.last_block_height < block.height) {
+            let metadata = ChainMedadata {
+                last_slot: block.slot,
+                last_block_height: block.height,
+            };
+            self.0.insert(LATEST_BLOCKHEIGHT_KEY, &metadata).await?;
+        }
+
         self.0.insert(&block_key, block).await?;
-        self.0.write().await?;
+        self.commit().await?;
         Ok(())
     }
 
-    async fn get_transactions(&self, address: &Address) -> Result<Vec<Transaction>> {
-        let tx_index = match self
+    async fn get_block(&self, height: u64) -> Result<Block> {
+        self.0
+            .data()
+            .await
+            .get(&db_key(DbKey::Block, &height))
+            .map_err(|_| Error::NotFound(format!("Block {:?} not found", height)))?
+            .into::<Block>()
+            .map_err(Error::from)
+    }
+
+    async fn get_latest_block(&self) -> Result<Block> {
+        let latest = self
             .0
             .data()
             .await
-            .get(db_key(DbKey::TransactionIndex, &address).as_ref())
-        {
-            Ok(tx_index) => tx_index.into::<Vec<TransactionIndex>>().unwrap_or_default(),
-            Err(_) => {
-                return Err(Error::StorageError(format!(
-                    "No transactions for {}",
-                    address
-                )))
-            }
-        };
+            .get(LATEST_BLOCKHEIGHT_KEY)
+            .map_err(|_| Error::NotFound(String::from("No blocks stored")))?
+            .into::<ChainMedadata>()?;
+        self.get_block(latest.last_block_height).await
+    }
 
-        let mut transactions = Vec::new();
-        for index in tx_index {
-            let block_key = db_key(DbKey::Block, &index.block_height);
-            let block: Block = self
-                .0
-                .data()
-                .await
-                .get(&block_key)
-                .map_err(|_| {
-                    Error::StorageError(format!("Block {:?} not found", index.block_height))
-                })?
-                .into::<Block>()
-                .unwrap();
+    async fn get_blocks(&self, query: &BlockQuery) -> Result<BlockPage> {
+        let data = self.0.data().await;
+        let heights = match data.get(BLOCK_HEIGHTS_KEY) {
+            Ok(heights) => heights.into::<Vec<u64>>().unwrap_or_default(),
+            Err(_) => Vec::new(),
+        };
 
-            transactions.push(block.transactions[index.index].clone());
+        let (selected, next_height) = select_heights(&heights, query);
+        let mut blocks = Vec::new();
+        for height in selected {
+            let block = data
+                .get(&db_key(DbKey::Block, &height))
+                .map_err(|_| Error::Inconsistent(format!("Block {:?} not found", height)))?
+                .into::<Block>()?;
+            blocks.push(block);
         }
 
-        Ok(transactions)
+        Ok(BlockPage {
+            blocks,
+            next_height,
+        })
+    }
+
+    async fn get_transactions(
+        &self,
+        address: &Address,
+        query: &TransactionQuery,
+    ) -> Result<TransactionPage> {
+        // a single snapshot serves the index and every block of the page
+        let data = self.0.data().await;
+        Self::transaction_page(&data, address, query, &mut HashMap::new())?
+            .ok_or_else(|| Error::NotFound(format!("No transactions for {}", address)))
+    }
+
+    async fn get_transactions_batch(
+        &self,
+        addresses: &[Address],
+        query: &TransactionQuery,
+    ) -> Result<Vec<TransactionPage>> {
+        let data = self.0.data().await;
+        let mut blocks = HashMap::new();
+        let mut pages = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            let page = Self::transaction_page(&data, address, query, &mut blocks)?;
+            pages.push(page.unwrap_or_default());
+        }
+        Ok(pages)
     }
 
     async fn get_account(&self, address: &Address) -> Result<Account> {
@@ -147,55 +534,645 @@ impl Storage for Database {
             .get(db_key(DbKey::AccountBalance, address).as_ref())
         {
             Ok(balance) => balance.into::<i64>().unwrap_or_default(),
-            Err(_) => {
-                return Err(Error::StorageError(format!(
-                    "Account {} not found",
-                    address
-                )))
-            }
+            Err(_) => return Err(Error::NotFound(format!("Account {} not found", address))),
         };
 
         Ok(Account {
-            address: address.clone(),
+            address: *address,
             balance,
         })
     }
+
+    async fn get_accounts(&self, addresses: &[Address]) -> Result<Vec<Option<Account>>> {
+        let data = self.0.data().await;
+        let accounts = addresses
+            .iter()
+            .map(|address| {
+                let balance = data.get(db_key(DbKey::AccountBalance, address).as_ref());
+                balance.ok().map(|balance| Account {
+                    address: *address,
+                    balance: balance.into::<i64>().unwrap_or_default(),
+                })
+            })
+            .collect();
+        Ok(accounts)
+    }
+
+    async fn get_account_at(&self, address: &Address, at: At) -> Result<Account> {
+        let history = match self
+            .0
+            .data()
+            .await
+            .get(db_key(DbKey::BalanceHistory, address).as_ref())
+        {
+            Ok(history) => history.into::<Vec<BalanceCheckpoint>>().unwrap_or_default(),
+            Err(_) => return Err(Error::NotFound(format!("Account {} not found", address))),
+        };
+
+        Ok(Account {
+            address: *address,
+            balance: balance_at(&history, at),
+        })
+    }
+
+    async fn get_balance_series(
+        &self,
+        address: &Address,
+        query: &SeriesQuery,
+    ) -> Result<Vec<BalanceBucket>> {
+        let series = match self
+            .0
+            .data()
+            .await
+            .get(&series_key(address, query.interval))
+        {
+            Ok(series) => series.into::<Vec<BalanceBucket>>().unwrap_or_default(),
+            Err(_) => return Err(Error::NotFound(format!("Account {} not found", address))),
+        };
+
+        select_series(&series, query)
+    }
+
+    async fn put_api_key(&mut self, key: &ApiKey) -> Result<()> {
+        let mut keys = self.stored_api_keys().await;
+        keys.insert(key.key.clone(), key.clone());
+        self.0.insert(API_KEYS_KEY, &keys).await?;
+        self.commit().await?;
+        Ok(())
+    }
+
+    async fn remove_api_key(&mut self, key: &str) -> Result<()> {
+        let mut keys = self.stored_api_keys().await;
+        if keys.remove(key).is_none() {
+            return Err(Error::NotFound(String::from("API key not found")));
+        }
+        self.0.insert(API_KEYS_KEY, &keys).await?;
+        self.commit().await?;
+        Ok(())
+    }
+
+    async fn get_api_keys(&self) -> Result<Vec<ApiKey>> {
+        Ok(self.stored_api_keys().await.into_values().collect())
+    }
+
+    async fn put_watch(&mut self, watch: &Watch) -> Result<()> {
+        let mut watches = self.stored_watches().await;
+        watches.insert(watch.address, watch.clone());
+        self.0.insert(WATCHES_KEY, &watches).await?;
+        self.commit().await?;
+        Ok(())
+    }
+
+    async fn remove_watch(&mut self, address: &Address) -> Result<()> {
+        let mut watches = self.stored_watches().await;
+        if watches.remove(address).is_none() {
+            return Err(Error::NotFound(format!(
+                "Address {} isn't watched",
+                address
+            )));
+        }
+        self.0.insert(WATCHES_KEY, &watches).await?;
+        self.commit().await?;
+        Ok(())
+    }
+
+    async fn get_watches(&self) -> Result<Vec<Watch>> {
+        Ok(self.stored_watches().await.into_values().collect())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        let data = self.0.data().await;
+        let heights = match data.get(BLOCK_HEIGHTS_KEY) {
+            Ok(heights) => heights.into::<Vec<u64>>().unwrap_or_default(),
+            Err(_) => Vec::new(),
+        };
+        if let Some(height) = heights.last() {
+            let block = data
+                .get(&db_key(DbKey::Block, height))
+                .map_err(|_| Error::Inconsistent(format!("Block {:?} not found", height)))?
+                .into::<Block>()?;
+            let metadata = ChainMedadata {
+                last_slot: block.slot,
+                last_block_height: block.height,
+            };
+            self.0.insert(LATEST_BLOCKHEIGHT_KEY, &metadata).await?;
+        }
+        self.commit().await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    blocks: BTreeMap<u64, Block>,
+    transaction_index: HashMap<Address, Vec<TransactionIndex>>,
+    balances: HashMap<Address, i64>,
+    balance_history: HashMap<Address, Vec<BalanceCheckpoint>>,
+    balance_series: HashMap<(Address, Interval), Vec<BalanceBucket>>,
+    api_keys: BTreeMap<String, ApiKey>,
+    watches: BTreeMap<Address, Watch>,
+}
+
+impl MemoryState {
+    /// Page of `address`'s transactions, `None` if it made none
+    fn transaction_page(
+        &self,
+        address: &Address,
+        query: &TransactionQuery,
+    ) -> Result<Option<TransactionPage>> {
+        let Some(tx_index) = self.transaction_index.get(address) else {
+            return Ok(None);
+        };
+
+        let (selected, next_cursor) = select_page(tx_index, query);
+        let cursors = selected.iter().map(|index| index.cursor()).collect();
+        let mut transactions = Vec::new();
+        for index in selected {
+            let block = self.blocks.get(&index.block_height).ok_or_else(|| {
+                Error::Inconsistent(format!("Block {:?} not found", index.block_height))
+            })?;
+            transactions.push(block.transactions[index.index].clone());
+        }
+
+        Ok(Some(TransactionPage {
+            transactions,
+            cursors,
+            next_cursor,
+        }))
+    }
+}
+
+/// Volatile [Storage] backend, everything is lost once the last clone is dropped
+/// Mirrors the semantics of [Database], useful for tests and throwaway runs
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryStorage(Arc<RwLock<MemoryState>>);
+impl InMemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for InMemoryStorage {
+    async fn add_block(&mut self, block: &Block) -> Result<()> {
+        let mut state = self.0.write().await;
+        if state.blocks.contains_key(&block.height) {
+            return Err(Error::BlockExists(block.height));
+        }
+
+        for (index, transaction) in block.transactions.iter().enumerate() {
+            // Record transactions done by sender in their transaction index
+            let tx_index = TransactionIndex {
+                block_height: block.height,
+                slot: block.slot,
+                timestamp: block.timestamp,
+                index,
+            };
+            let sender_index = state
+                .transaction_index
+                .entry(transaction.source)
+                .or_default();
+            insert_index(sender_index, tx_index);
+
+            *state.balances.entry(transaction.source).or_default() -= transaction.amount as i64;
+            *state.balances.entry(transaction.destination).or_default() +=
+                transaction.amount as i64;
+        }
+
+        for (address, flow) in account_flows(block) {
+            for interval in Interval::ALL {
+                let series = state.balance_series.entry((address, interval)).or_default();
+                record_bucket(series, interval, block, &flow);
+            }
+            let history = state.balance_history.entry(address).or_default();
+            record_checkpoint(history, block, flow.delta());
+        }
+        state.blocks.insert(block.height, block.clone());
+        Ok(())
+    }
+
+    async fn get_block(&self, height: u64) -> Result<Block> {
+        let state = self.0.read().await;
+        state
+            .blocks
+            .get(&height)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("Block {:?} not found", height)))
+    }
+
+    async fn get_latest_block(&self) -> Result<Block> {
+        let state = self.0.read().await;
+        state
+            .blocks
+            .last_key_value()
+            .map(|(_, block)| block.clone())
+            .ok_or_else(|| Error::NotFound(String::from("No blocks stored")))
+    }
+
+    async fn get_blocks(&self, query: &BlockQuery) -> Result<BlockPage> {
+        let state = self.0.read().await;
+        let range = (
+            query.from.map_or(Bound::Unbounded, Bound::Included),
+            query.to.map_or(Bound::Unbounded, Bound::Included),
+        );
+        if query.from.zip(query.to).is_some_and(|(from, to)| from > to) {
+            return Ok(BlockPage::default());
+        }
+
+        let mut blocks: Vec<Block> = state
+            .blocks
+            .range(range)
+            .take(query.limit.saturating_add(1))
+            .map(|(_, block)| block.clone())
+            .collect();
+        let next_height = if blocks.len() > query.limit {
+            blocks.pop().map(|block| block.height)
+        } else {
+            None
+        };
+
+        Ok(BlockPage {
+            blocks,
+            next_height,
+        })
+    }
+
+    async fn get_transactions(
+        &self,
+        address: &Address,
+        query: &TransactionQuery,
+    ) -> Result<TransactionPage> {
+        let state = self.0.read().await;
+        state
+            .transaction_page(address, query)?
+            .ok_or_else(|| Error::NotFound(format!("No transactions for {}", address)))
+    }
+
+    async fn get_transactions_batch(
+        &self,
+        addresses: &[Address],
+        query: &TransactionQuery,
+    ) -> Result<Vec<TransactionPage>> {
+        let state = self.0.read().await;
+        let mut pages = Vec::with_capacity(addresses.len());
+        for address in addresses {
+            let page = state.transaction_page(address, query)?;
+            pages.push(page.unwrap_or_default());
+        }
+        Ok(pages)
+    }
+
+    async fn get_account(&self, address: &Address) -> Result<Account> {
+        let state = self.0.read().await;
+        let balance = state
+            .balances
+            .get(address)
+            .ok_or_else(|| Error::NotFound(format!("Account {} not found", address)))?;
+
+        Ok(Account {
+            address: *address,
+            balance: *balance,
+        })
+    }
+
+    async fn get_accounts(&self, addresses: &[Address]) -> Result<Vec<Option<Account>>> {
+        let state = self.0.read().await;
+        let accounts = addresses
+            .iter()
+            .map(|address| {
+                state.balances.get(address).map(|balance| Account {
+                    address: *address,
+                    balance: *balance,
+                })
+            })
+            .collect();
+        Ok(accounts)
+    }
+
+    async fn get_account_at(&self, address: &Address, at: At) -> Result<Account> {
+        let state = self.0.read().await;
+        let history = state
+            .balance_history
+            .get(address)
+            .ok_or_else(|| Error::NotFound(format!("Account {} not found", address)))?;
+
+        Ok(Account {
+            address: *address,
+            balance: balance_at(history, at),
+        })
+    }
+
+    async fn get_balance_series(
+        &self,
+        address: &Address,
+        query: &SeriesQuery,
+    ) -> Result<Vec<BalanceBucket>> {
+        let state = self.0.read().await;
+        let series = state
+            .balance_series
+            .get(&(*address, query.interval))
+            .ok_or_else(|| Error::NotFound(format!("Account {} not found", address)))?;
+
+        select_series(series, query)
+    }
+
+    async fn put_api_key(&mut self, key: &ApiKey) -> Result<()> {
+        let mut state = self.0.write().await;
+        state.api_keys.insert(key.key.clone(), key.clone());
+        Ok(())
+    }
+
+    async fn remove_api_key(&mut self, key: &str) -> Result<()> {
+        let mut state = self.0.write().await;
+        match state.api_keys.remove(key) {
+            Some(_) => Ok(()),
+            None => Err(Error::NotFound(String::from("API key not found"))),
+        }
+    }
+
+    async fn get_api_keys(&self) -> Result<Vec<ApiKey>> {
+        let state = self.0.read().await;
+        Ok(state.api_keys.values().cloned().collect())
+    }
+
+    async fn put_watch(&mut self, watch: &Watch) -> Result<()> {
+        let mut state = self.0.write().await;
+        state.watches.insert(watch.address, watch.clone());
+        Ok(())
+    }
+
+    async fn remove_watch(&mut self, address: &Address) -> Result<()> {
+        let mut state = self.0.write().await;
+        match state.watches.remove(address) {
+            Some(_) => Ok(()),
+            None => Err(Error::NotFound(format!(
+                "Address {} isn't watched",
+                address
+            ))),
+        }
+    }
+
+    async fn get_watches(&self) -> Result<Vec<Watch>> {
+        let state = self.0.read().await;
+        Ok(state.watches.values().cloned().collect())
+    }
+
+    /// Nothing to persist, the latest block is always the highest stored
+    async fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Path of the generation a [Database] bumps on every commit
+fn generation_path(path: &str) -> String {
+    format!("{}.generation", path)
+}
+
+/// Removes a database file along with its generation
+#[cfg(test)]
+pub(crate) fn remove_database(path: impl AsRef<std::path::Path>) {
+    let path = path.as_ref();
+    std::fs::remove_file(path).unwrap();
+    // absent when nothing was committed
+    let _ = std::fs::remove_file(generation_path(&path.to_string_lossy()));
+}
+
+async fn read_generation(path: &str) -> Option<u64> {
+    tokio::fs::read_to_string(path)
+        .await
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Version of a database file, changes whenever the file is committed
+/// The generation catches writes within the filesystem's mtime resolution that keep the
+/// length, mtime and length cover files written without one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileVersion {
+    generation: Option<u64>,
+    modified: Option<(SystemTime, u64)>,
+}
+
+impl FileVersion {
+    async fn of(path: &str) -> Self {
+        let modified = tokio::fs::metadata(path)
+            .await
+            .ok()
+            .and_then(|metadata| Some((metadata.modified().ok()?, metadata.len())));
+        Self {
+            generation: read_generation(&generation_path(path)).await,
+            modified,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Snapshot {
+    db: Database,
+    /// Version of the file `db` was loaded from
+    version: FileVersion,
+}
+
+/// Read-only view of a [Database] file written by another process, e.g. for API replicas
+/// Reads check whether the file was committed since it was last loaded and reload it if so.
+/// A read that finds a new version waits for the reload, reads arriving while it runs are
+/// served the previous snapshot, as are reads after a failed reload (e.g. of a file caught
+/// halfway through a write) until a later read loads it. Writes fail
+#[derive(Debug, Clone)]
+pub struct Replica {
+    path: String,
+    snapshot: Arc<RwLock<Snapshot>>,
+    /// Held while reloading, so the file is parsed once per version
+    reloading: Arc<Mutex<()>>,
+}
+
+impl Replica {
+    pub fn open(path: &str) -> Result<Self> {
+        let modified = std::fs::metadata(path)
+            .ok()
+            .and_then(|metadata| Some((metadata.modified().ok()?, metadata.len())));
+        let generation = std::fs::read_to_string(generation_path(path))
+            .ok()
+            .and_then(|generation| generation.trim().parse().ok());
+        let db = Database::new(path)?;
+        Ok(Self {
+            path: path.to_string(),
+            snapshot: Arc::new(RwLock::new(Snapshot {
+                db,
+                version: FileVersion {
+                    generation,
+                    modified,
+                },
+            })),
+            reloading: Arc::new(Mutex::new(())),
+        })
+    }
+
+    /// Latest snapshot of the database, reloaded if the file changed
+    async fn db(&self) -> Database {
+        let version = FileVersion::of(&self.path).await;
+        {
+            let snapshot = self.snapshot.read().await;
+            if snapshot.version == version {
+                return snapshot.db.clone();
+            }
+        }
+
+        // parsed outside the snapshot lock, reads meanwhile are served the current snapshot
+        let Ok(_reloading) = self.reloading.try_lock() else {
+            return self.snapshot.read().await.db.clone();
+        };
+        // another read may have reloaded it meanwhile
+        if self.snapshot.read().await.version != version {
+            let path = self.path.clone();
+            match tokio::task::spawn_blocking(move || Database::new(&path)).await {
+                Ok(Ok(db)) => {
+                    tracing::debug!("Reloaded {}", self.path);
+                    *self.snapshot.write().await = Snapshot { db, version };
+                }
+                // most likely read while being written, the previous snapshot is served until the next read
+                Ok(Err(error)) => tracing::warn!("Failed to reload {}: {}", self.path, error),
+                Err(error) => tracing::warn!("Failed to reload {}: {}", self.path, error),
+            }
+        }
+        self.snapshot.read().await.db.clone()
+    }
+}
+
+impl Storage for Replica {
+    async fn add_block(&mut self, _block: &Block) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    async fn get_block(&self, height: u64) -> Result<Block> {
+        self.db().await.get_block(height).await
+    }
+
+    async fn get_latest_block(&self) -> Result<Block> {
+        self.db().await.get_latest_block().await
+    }
+
+    async fn get_blocks(&self, query: &BlockQuery) -> Result<BlockPage> {
+        self.db().await.get_blocks(query).await
+    }
+
+    async fn get_transactions(
+        &self,
+        address: &Address,
+        query: &TransactionQuery,
+    ) -> Result<TransactionPage> {
+        self.db().await.get_transactions(address, query).await
+    }
+
+    async fn get_transactions_batch(
+        &self,
+        addresses: &[Address],
+        query: &TransactionQuery,
+    ) -> Result<Vec<TransactionPage>> {
+        self.db()
+            .await
+            .get_transactions_batch(addresses, query)
+            .await
+    }
+
+    async fn get_account(&self, address: &Address) -> Result<Account> {
+        self.db().await.get_account(address).await
+    }
+
+    async fn get_accounts(&self, addresses: &[Address]) -> Result<Vec<Option<Account>>> {
+        self.db().await.get_accounts(addresses).await
+    }
+
+    async fn get_account_at(&self, address: &Address, at: At) -> Result<Account> {
+        self.db().await.get_account_at(address, at).await
+    }
+
+    async fn get_balance_series(
+        &self,
+        address: &Address,
+        query: &SeriesQuery,
+    ) -> Result<Vec<BalanceBucket>> {
+        self.db().await.get_balance_series(address, query).await
+    }
+
+    async fn put_api_key(&mut self, _key: &ApiKey) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    async fn remove_api_key(&mut self, _key: &str) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    async fn get_api_keys(&self) -> Result<Vec<ApiKey>> {
+        self.db().await.get_api_keys().await
+    }
+
+    async fn put_watch(&mut self, _watch: &Watch) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    async fn remove_watch(&mut self, _address: &Address) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    async fn get_watches(&self) -> Result<Vec<Watch>> {
+        self.db().await.get_watches().await
+    }
+
+    /// Nothing to persist
+    async fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod storage_tests {
-    use crate::storage::Database;
+    use crate::error::Error;
+    use crate::storage::{remove_database, Database, InMemoryStorage, Replica, MAX_SERIES_BUCKETS};
     use crate::traits::Storage;
     use crate::types::*;
     use rand::Rng;
+    use solana_program::pubkey::Pubkey;
 
-    #[tokio::test]
-    async fn sanity_check() {
-        let path = "/tmp/storage.json";
-        let mut db = Database::new(path).unwrap();
+    fn new_address() -> Address {
+        Pubkey::new_unique().into()
+    }
 
+    async fn sanity_check<S: Storage>(mut db: S) {
         let mut rng = rand::thread_rng();
         let block_height: u64 = rng.gen_range(0..10000000);
-        let source = String::from(format!("source{}", block_height));
-        let destination = String::from(format!("destination{}", block_height));
-        let block_hash = String::from(format!("block_hash{}", block_height));
+        let source = new_address();
+        let destination = new_address();
+        let block_hash = format!("block_hash{}", block_height);
 
         let amount = 100;
         let transaction = Transaction {
-            source: source.to_string(),
-            destination: destination.to_string(),
+            source,
+            destination,
             amount,
         };
 
         let block = Block {
             height: block_height,
+            slot: block_height,
             transactions: vec![transaction],
             hash: block_hash.to_string(),
             timestamp: 100100,
         };
         db.add_block(&block).await.unwrap();
+        assert!(db.add_block(&block).await.is_err());
 
-        let transactions = db.get_transactions(&source).await.unwrap();
+        let query = TransactionQuery::default();
+        let transactions = db
+            .get_transactions(&source, &query)
+            .await
+            .unwrap()
+            .transactions;
         assert_eq!(transactions.len(), 1);
         assert_eq!(transactions[0].amount, amount);
         assert_eq!(transactions[0].source, source);
@@ -203,5 +1180,597 @@ mod storage_tests {
 
         let account = db.get_account(&destination).await.unwrap();
         assert_eq!(account.balance, amount as i64);
+        let account = db.get_account(&source).await.unwrap();
+        assert_eq!(account.balance, -(amount as i64));
+
+        assert!(db.get_transactions(&destination, &query).await.is_err());
+        assert!(db.get_account(&new_address()).await.is_err());
+
+        let missing = new_address();
+        let accounts = db
+            .get_accounts(&[destination, missing, source])
+            .await
+            .unwrap();
+        let balances: Vec<_> = accounts
+            .iter()
+            .map(|account| account.as_ref().map(|account| account.balance))
+            .collect();
+        assert_eq!(
+            balances,
+            vec![Some(amount as i64), None, Some(-(amount as i64))]
+        );
+
+        let pages = db
+            .get_transactions_batch(&[source, destination], &query)
+            .await
+            .unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].transactions, transactions);
+        assert!(pages[1].transactions.is_empty());
+    }
+
+    async fn pagination_check<S: Storage>(mut db: S) {
+        let source = new_address();
+        let destination = new_address();
+        // blocks are added out of order, 3 transfers each
+        for height in [3u64, 1, 2] {
+            let transactions = (0..3)
+                .map(|i| Transaction {
+                    source,
+                    destination,
+                    amount: height * 10 + i,
+                })
+                .collect();
+            let block = Block {
+                height,
+                slot: height + 100,
+                hash: format!("block_hash{}", height),
+                timestamp: height as i64,
+                transactions,
+            };
+            db.add_block(&block).await.unwrap();
+        }
+
+        let amounts = |page: &TransactionPage| {
+            page.transactions
+                .iter()
+                .map(|t| t.amount)
+                .collect::<Vec<_>>()
+        };
+
+        // newest first by default
+        let mut query = TransactionQuery {
+            limit: 4,
+            ..Default::default()
+        };
+        let page = db.get_transactions(&source, &query).await.unwrap();
+        assert_eq!(amounts(&page), vec![32, 31, 30, 22]);
+        query.cursor = page.next_cursor;
+        let page = db.get_transactions(&source, &query).await.unwrap();
+        assert_eq!(amounts(&page), vec![21, 20, 12, 11]);
+        query.cursor = page.next_cursor;
+        let page = db.get_transactions(&source, &query).await.unwrap();
+        assert_eq!(amounts(&page), vec![10]);
+        assert_eq!(page.next_cursor, None);
+
+        // oldest first
+        let mut query = TransactionQuery {
+            limit: 5,
+            order: Order::Asc,
+            ..Default::default()
+        };
+        let page = db.get_transactions(&source, &query).await.unwrap();
+        assert_eq!(amounts(&page), vec![10, 11, 12, 20, 21]);
+        query.cursor = page.next_cursor;
+        let page = db.get_transactions(&source, &query).await.unwrap();
+        assert_eq!(amounts(&page), vec![22, 30, 31, 32]);
+        assert_eq!(page.next_cursor, None);
+
+        // exact fit doesn't hand out a cursor to an empty page
+        query.limit = 9;
+        query.cursor = None;
+        let page = db.get_transactions(&source, &query).await.unwrap();
+        assert_eq!(page.transactions.len(), 9);
+        assert_eq!(page.next_cursor, None);
+    }
+
+    async fn range_check<S: Storage>(mut db: S) {
+        let source = new_address();
+        let destination = new_address();
+        // block time advances by an hour per block
+        for height in 1..=10u64 {
+            let block = Block {
+                height,
+                slot: height * 2,
+                hash: format!("block_hash{}", height),
+                timestamp: height as i64 * 3600,
+                transactions: vec![Transaction {
+                    source,
+                    destination,
+                    amount: height,
+                }],
+            };
+            db.add_block(&block).await.unwrap();
+        }
+
+        let amounts = |page: TransactionPage| {
+            page.transactions
+                .iter()
+                .map(|t| t.amount)
+                .collect::<Vec<_>>()
+        };
+
+        let query = TransactionQuery {
+            order: Order::Asc,
+            from_slot: Some(5),
+            to_slot: Some(12),
+            ..Default::default()
+        };
+        let page = db.get_transactions(&source, &query).await.unwrap();
+        assert_eq!(amounts(page), vec![3, 4, 5, 6]);
+
+        let query = TransactionQuery {
+            order: Order::Asc,
+            from_time: Some(7 * 3600),
+            ..Default::default()
+        };
+        let page = db.get_transactions(&source, &query).await.unwrap();
+        assert_eq!(amounts(page), vec![7, 8, 9, 10]);
+
+        // both bounds apply, pagination works within the range
+        let mut query = TransactionQuery {
+            limit: 2,
+            from_slot: Some(4),
+            to_time: Some(6 * 3600),
+            ..Default::default()
+        };
+        let page = db.get_transactions(&source, &query).await.unwrap();
+        query.cursor = page.next_cursor;
+        assert_eq!(amounts(page), vec![6, 5]);
+        let page = db.get_transactions(&source, &query).await.unwrap();
+        query.cursor = page.next_cursor;
+        assert_eq!(amounts(page), vec![4, 3]);
+        let page = db.get_transactions(&source, &query).await.unwrap();
+        assert_eq!(amounts(page), vec![2]);
+
+        // empty range
+        let query = TransactionQuery {
+            from_slot: Some(12),
+            to_slot: Some(4),
+            ..Default::default()
+        };
+        let page = db.get_transactions(&source, &query).await.unwrap();
+        assert!(page.transactions.is_empty());
+    }
+
+    async fn history_check<S: Storage>(mut db: S) {
+        let alice = new_address();
+        let bob = new_address();
+        let transfer = |source: &Address, destination: &Address, amount| Transaction {
+            source: *source,
+            destination: *destination,
+            amount,
+        };
+        let block = |height: u64, transactions| Block {
+            height,
+            slot: height * 10,
+            hash: format!("block_hash{}", height),
+            timestamp: height as i64 * 100,
+            transactions,
+        };
+
+        // block 2 arrives last, later checkpoints must account for it
+        db.add_block(&block(1, vec![transfer(&alice, &bob, 50)]))
+            .await
+            .unwrap();
+        db.add_block(&block(
+            3,
+            vec![transfer(&bob, &alice, 20), transfer(&bob, &alice, 5)],
+        ))
+        .await
+        .unwrap();
+        db.add_block(&block(2, vec![transfer(&alice, &bob, 10)]))
+            .await
+            .unwrap();
+
+        let balance = |account: Account| account.balance;
+        let bob_at = |at| db.get_account_at(&bob, at);
+        assert_eq!(balance(bob_at(At::Slot(5)).await.unwrap()), 0);
+        assert_eq!(balance(bob_at(At::Slot(10)).await.unwrap()), 50);
+        assert_eq!(balance(bob_at(At::Slot(29)).await.unwrap()), 60);
+        assert_eq!(balance(bob_at(At::Slot(30)).await.unwrap()), 35);
+        assert_eq!(balance(bob_at(At::Timestamp(250)).await.unwrap()), 60);
+        assert_eq!(balance(bob_at(At::Timestamp(i64::MAX)).await.unwrap()), 35);
+
+        let alice_now = db.get_account(&alice).await.unwrap();
+        let alice_latest = db.get_account_at(&alice, At::Slot(u64::MAX)).await.unwrap();
+        assert_eq!(alice_now, alice_latest);
+        assert_eq!(alice_now.balance, -35);
+
+        assert!(db
+            .get_account_at(&new_address(), At::Slot(10))
+            .await
+            .is_err());
+    }
+
+    async fn series_check<S: Storage>(mut db: S) {
+        let alice = new_address();
+        let bob = new_address();
+        let hour = Interval::Hour.seconds();
+        let block =
+            |height: u64, timestamp: i64, source: &Address, destination: &Address, amount| Block {
+                height,
+                slot: height,
+                hash: format!("block_hash{}", height),
+                timestamp,
+                transactions: vec![Transaction {
+                    source: *source,
+                    destination: *destination,
+                    amount,
+                }],
+            };
+
+        // two blocks in the first hour, none in the second, block 3 arrives late
+        db.add_block(&block(1, 10, &alice, &bob, 100))
+            .await
+            .unwrap();
+        db.add_block(&block(4, 3 * hour, &alice, &bob, 7))
+            .await
+            .unwrap();
+        db.add_block(&block(2, 20, &bob, &alice, 30)).await.unwrap();
+        db.add_block(&block(3, 2 * hour + 5, &alice, &bob, 1))
+            .await
+            .unwrap();
+
+        let query = SeriesQuery {
+            interval: Interval::Hour,
+            ..Default::default()
+        };
+        let series = db.get_balance_series(&bob, &query).await.unwrap();
+        let expected = vec![
+            (0, 100, 30, 70),
+            (hour, 0, 0, 70),
+            (2 * hour, 1, 0, 71),
+            (3 * hour, 7, 0, 78),
+        ];
+        let actual = series
+            .iter()
+            .map(|b| (b.start, b.inflow, b.outflow, b.balance))
+            .collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+
+        // bounds are widened to whole buckets, balance carries over from before the range
+        let query = SeriesQuery {
+            interval: Interval::Hour,
+            from_time: Some(hour + 1),
+            to_time: Some(5 * hour - 1),
+        };
+        let series = db.get_balance_series(&bob, &query).await.unwrap();
+        assert_eq!(series.len(), 4);
+        assert_eq!(series[0].balance, 70);
+        assert_eq!(series[3].start, 4 * hour);
+        assert_eq!(series[3].balance, 78);
+
+        let query = SeriesQuery::default();
+        let series = db.get_balance_series(&alice, &query).await.unwrap();
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].inflow, 30);
+        assert_eq!(series[0].outflow, 108);
+        assert_eq!(series[0].balance, -78);
+
+        let query = SeriesQuery {
+            interval: Interval::Hour,
+            from_time: Some(0),
+            to_time: Some(i64::MAX / 2),
+        };
+        assert!(db.get_balance_series(&alice, &query).await.is_err());
+        let query = SeriesQuery {
+            interval: Interval::Hour,
+            from_time: Some(i64::MIN),
+            to_time: Some(i64::MAX),
+        };
+        assert!(matches!(
+            db.get_balance_series(&alice, &query).await,
+            Err(Error::InvalidInput(_))
+        ));
+        let query = SeriesQuery {
+            interval: Interval::Day,
+            from_time: Some(i64::MIN),
+            to_time: None,
+        };
+        assert!(db.get_balance_series(&alice, &query).await.is_ok());
+        // without bounds, long histories are clamped to their latest buckets
+        let carol = new_address();
+        db.add_block(&block(5, 20_000 * hour, &alice, &carol, 1))
+            .await
+            .unwrap();
+        let query = SeriesQuery {
+            interval: Interval::Hour,
+            ..Default::default()
+        };
+        let series = db.get_balance_series(&alice, &query).await.unwrap();
+        assert_eq!(series.len(), MAX_SERIES_BUCKETS as usize);
+        assert_eq!(series.last().unwrap().start, 20_000 * hour);
+        assert_eq!(series.last().unwrap().balance, -79);
+    }
+
+    async fn blocks_check<S: Storage>(mut db: S) {
+        assert!(db.get_latest_block().await.is_err());
+        assert!(db
+            .get_blocks(&BlockQuery::default())
+            .await
+            .unwrap()
+            .blocks
+            .is_empty());
+
+        // heights 10..=15 without 12, out of order
+        for height in [11u64, 10, 15, 13, 14] {
+            let block = Block {
+                height,
+                slot: height + 100,
+                hash: format!("block_hash{}", height),
+                timestamp: height as i64,
+                transactions: Vec::new(),
+            };
+            db.add_block(&block).await.unwrap();
+        }
+
+        assert_eq!(db.get_block(13).await.unwrap().hash, "block_hash13");
+        assert!(db.get_block(12).await.is_err());
+        let latest = db.get_latest_block().await.unwrap();
+        assert_eq!((latest.height, latest.slot), (15, 115));
+        db.flush().await.unwrap();
+        let latest = db.get_latest_block().await.unwrap();
+        assert_eq!((latest.height, latest.slot), (15, 115));
+
+        let heights = |page: &BlockPage| page.blocks.iter().map(|b| b.height).collect::<Vec<_>>();
+        let mut query = BlockQuery {
+            from: Some(11),
+            to: Some(14),
+            limit: 2,
+        };
+        let page = db.get_blocks(&query).await.unwrap();
+        assert_eq!(heights(&page), vec![11, 13]);
+        assert_eq!(page.next_height, Some(14));
+        query.from = page.next_height;
+        let page = db.get_blocks(&query).await.unwrap();
+        assert_eq!(heights(&page), vec![14]);
+        assert_eq!(page.next_height, None);
+
+        let page = db.get_blocks(&BlockQuery::default()).await.unwrap();
+        assert_eq!(heights(&page), vec![10, 11, 13, 14, 15]);
+
+        let query = BlockQuery {
+            from: Some(14),
+            to: Some(11),
+            ..Default::default()
+        };
+        assert!(db.get_blocks(&query).await.unwrap().blocks.is_empty());
+    }
+
+    async fn api_keys_check<S: Storage>(mut db: S) {
+        assert!(db.get_api_keys().await.unwrap().is_empty());
+        let mut key = ApiKey {
+            key: String::from("key"),
+            name: String::from("partner"),
+            quota: Some(10),
+            admin: false,
+        };
+        db.put_api_key(&key).await.unwrap();
+        key.quota = None;
+        db.put_api_key(&key).await.unwrap();
+        assert_eq!(db.get_api_keys().await.unwrap(), vec![key]);
+
+        db.remove_api_key("key").await.unwrap();
+        assert!(db.get_api_keys().await.unwrap().is_empty());
+        assert!(db.remove_api_key("key").await.is_err());
+    }
+
+    async fn watches_check<S: Storage>(mut db: S) {
+        assert!(db.get_watches().await.unwrap().is_empty());
+        let mut watch = Watch {
+            address: new_address(),
+            min_amount: None,
+        };
+        db.put_watch(&watch).await.unwrap();
+        watch.min_amount = Some(1000);
+        db.put_watch(&watch).await.unwrap();
+        assert_eq!(db.get_watches().await.unwrap(), vec![watch.clone()]);
+
+        db.remove_watch(&watch.address).await.unwrap();
+        assert!(db.get_watches().await.unwrap().is_empty());
+        assert!(matches!(
+            db.remove_watch(&watch.address).await,
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    fn temp_db_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("storage-{}.json", rand::thread_rng().gen::<u64>()))
+    }
+
+    #[tokio::test]
+    async fn database_sanity_check() {
+        let path = temp_db_path();
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        sanity_check(db).await;
+        remove_database(path);
+    }
+
+    #[tokio::test]
+    async fn in_memory_sanity_check() {
+        sanity_check(InMemoryStorage::new()).await;
+    }
+
+    #[tokio::test]
+    async fn database_pagination() {
+        let path = temp_db_path();
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        pagination_check(db).await;
+        remove_database(path);
+    }
+
+    #[tokio::test]
+    async fn in_memory_pagination() {
+        pagination_check(InMemoryStorage::new()).await;
+    }
+
+    #[tokio::test]
+    async fn database_range() {
+        let path = temp_db_path();
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        range_check(db).await;
+        remove_database(path);
+    }
+
+    #[tokio::test]
+    async fn in_memory_range() {
+        range_check(InMemoryStorage::new()).await;
+    }
+
+    #[tokio::test]
+    async fn database_history() {
+        let path = temp_db_path();
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        history_check(db).await;
+        remove_database(path);
+    }
+
+    #[tokio::test]
+    async fn in_memory_history() {
+        history_check(InMemoryStorage::new()).await;
+    }
+
+    #[tokio::test]
+    async fn database_series() {
+        let path = temp_db_path();
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        series_check(db).await;
+        remove_database(path);
+    }
+
+    #[tokio::test]
+    async fn in_memory_series() {
+        series_check(InMemoryStorage::new()).await;
+    }
+
+    #[tokio::test]
+    async fn database_blocks() {
+        let path = temp_db_path();
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        blocks_check(db).await;
+        remove_database(path);
+    }
+
+    /// Flushed blocks are there once the file is opened again
+    #[tokio::test]
+    async fn database_flush() {
+        let path = temp_db_path();
+        let mut db = Database::new(path.to_str().unwrap()).unwrap();
+        for height in [2u64, 1] {
+            let block = Block {
+                height,
+                slot: height + 100,
+                hash: format!("block_hash{}", height),
+                timestamp: height as i64,
+                transactions: Vec::new(),
+            };
+            db.add_block(&block).await.unwrap();
+        }
+        db.flush().await.unwrap();
+
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        let latest = db.get_latest_block().await.unwrap();
+        assert_eq!((latest.height, latest.slot), (2, 102));
+        remove_database(path);
+    }
+
+    #[tokio::test]
+    async fn replica() {
+        let path = temp_db_path();
+        let path = path.to_str().unwrap();
+        let mut writer = Database::new(path).unwrap();
+        let mut replica = Replica::open(path).unwrap();
+        assert!(matches!(
+            replica.get_latest_block().await,
+            Err(Error::NotFound(_))
+        ));
+
+        // blocks committed by the writer are picked up
+        for height in 1..=2 {
+            let block = Block {
+                height,
+                slot: height + 100,
+                hash: format!("block_hash{}", height),
+                timestamp: height as i64,
+                transactions: Vec::new(),
+            };
+            writer.add_block(&block).await.unwrap();
+            assert_eq!(replica.get_latest_block().await.unwrap().height, height);
+            assert!(matches!(
+                replica.add_block(&block).await,
+                Err(Error::ReadOnly)
+            ));
+        }
+
+        // rewrites keeping the length and mtime are picked up through the generation
+        let mut watch = Watch {
+            address: new_address(),
+            min_amount: Some(5),
+        };
+        writer.put_watch(&watch).await.unwrap();
+        assert_eq!(replica.get_watches().await.unwrap(), vec![watch.clone()]);
+        let metadata = std::fs::metadata(path).unwrap();
+        watch.min_amount = Some(6);
+        writer.put_watch(&watch).await.unwrap();
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(metadata.modified().unwrap()).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), metadata.len());
+        assert_eq!(replica.get_watches().await.unwrap(), vec![watch]);
+
+        // a file caught halfway through a write keeps the previous snapshot
+        std::fs::write(path, "{\"latest_bh\":").unwrap();
+        assert_eq!(replica.get_latest_block().await.unwrap().height, 2);
+        remove_database(path);
+    }
+
+    #[tokio::test]
+    async fn in_memory_blocks() {
+        blocks_check(InMemoryStorage::new()).await;
+    }
+
+    #[tokio::test]
+    async fn database_api_keys() {
+        let path = temp_db_path();
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        api_keys_check(db).await;
+        remove_database(path);
+    }
+
+    #[tokio::test]
+    async fn in_memory_api_keys() {
+        api_keys_check(InMemoryStorage::new()).await;
+    }
+
+    #[tokio::test]
+    async fn database_watches() {
+        let path = temp_db_path();
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        watches_check(db).await;
+        // reopened from the file
+        let mut db = Database::new(path.to_str().unwrap()).unwrap();
+        let watch = Watch {
+            address: new_address(),
+            min_amount: Some(5),
+        };
+        db.put_watch(&watch).await.unwrap();
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(db.get_watches().await.unwrap(), vec![watch]);
+        remove_database(path);
+    }
+
+    #[tokio::test]
+    async fn in_memory_watches() {
+        watches_check(InMemoryStorage::new()).await;
     }
 }
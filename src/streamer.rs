@@ -1,5 +1,10 @@
+use crate::config::Filters;
 use crate::error::*;
+use crate::metrics::Metrics;
+use crate::queue::{QueuedSlot, SlotReceiver};
 use crate::{traits::BlockStream, types::*};
+use futures_util::stream::FuturesOrdered;
+use futures_util::StreamExt;
 use solana_client::client_error::ClientErrorKind;
 use solana_client::rpc_request::RpcError;
 use solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcBlockConfig};
@@ -8,9 +13,12 @@ use solana_transaction_status::{
     EncodedTransaction, EncodedTransactionWithStatusMeta, UiConfirmedBlock, UiInstruction,
     UiMessage, UiParsedInstruction,
 };
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::sync::mpsc::UnboundedReceiver;
+use std::time::Instant;
 use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, Span};
 
 /// [Reference](https://support.quicknode.com/hc/en-us/articles/16459608696721-Solana-RPC-Error-Code-Reference)
 const BLOCK_NOT_AVAILABLE: i64 = -32004;
@@ -24,8 +32,8 @@ pub fn parse_instruction(instruction: &UiInstruction) -> Option<Transaction> {
         {
             let info = parsed_instruction.parsed.get("info")?.as_object()?;
             return Some(Transaction {
-                source: info.get("source")?.as_str()?.to_string(),
-                destination: info.get("destination")?.as_str()?.to_string(),
+                source: info.get("source")?.as_str()?.parse().ok()?,
+                destination: info.get("destination")?.as_str()?.parse().ok()?,
                 amount: info.get("lamports")?.as_number()?.as_u64()?,
             });
         }
@@ -65,8 +73,10 @@ impl From<UiConfirmedBlock> for Block {
                 }
             }
         };
+        // slot isn't part of the RPC response, callers know it
         Self {
             height: block.block_height.unwrap(),
+            slot: 0,
             hash: block.blockhash,
             transactions,
             timestamp: block.block_time.unwrap(),
@@ -74,75 +84,158 @@ impl From<UiConfirmedBlock> for Block {
     }
 }
 
+/// Fetches and parses blocks, shared by the fetches in flight
+struct Fetcher {
+    client: RpcClient,
+    block_config: RpcBlockConfig,
+    filters: Filters,
+    metrics: Metrics,
+}
+
+impl Fetcher {
+    async fn fetch_block(&self, slot: Slot) -> Result<Block> {
+        let start = Instant::now();
+        let block = self
+            .client
+            .get_block_with_config(slot, self.block_config)
+            .instrument(tracing::info_span!("fetch"))
+            .await;
+        self.metrics
+            .rpc_latency
+            .with_label_values(&["getBlock"])
+            .observe(start.elapsed().as_secs_f64());
+        let block = block.map_err(|error| {
+            if let ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) = error.kind()
+            {
+                if code == &BLOCK_NOT_AVAILABLE {
+                    return Error::SlotMissing(slot);
+                }
+                if code == &SLOT_SKIPPED {
+                    return Error::SlotSkipped(slot);
+                }
+            }
+            Error::RpcError(Box::new(error))
+        });
+
+        match block {
+            Ok(block) => Ok(tracing::info_span!("parse").in_scope(|| {
+                let mut block = Block {
+                    slot,
+                    ..Block::from(block)
+                };
+                block
+                    .transactions
+                    .retain(|transaction| self.filters.matches(transaction));
+                block
+            })),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// A block being fetched, along with the span of it's slot
+type Fetch = Pin<Box<dyn Future<Output = (Result<Block>, Span)> + Send>>;
+
 /// Fetches block data and parses it into storeable types
+/// Up to `concurrency` blocks are fetched at once, they're streamed in the order their slots were queued
 pub struct Streamer {
-    client: Arc<RpcClient>,
-    block_config: Arc<RpcBlockConfig>,
-    slot_monitor: UnboundedReceiver<Slot>,
+    fetcher: Arc<Fetcher>,
+    slot_monitor: SlotReceiver,
     token: CancellationToken,
+    concurrency: usize,
+    in_flight: FuturesOrdered<Fetch>,
 }
 
 impl Streamer {
+    /// Only transfers matching `filters` are kept in the streamed blocks
     pub async fn new(
         rpc_url: &str,
         token: CancellationToken,
-        slot_monitor: UnboundedReceiver<Slot>,
+        slot_monitor: SlotReceiver,
         block_config: RpcBlockConfig,
+        concurrency: usize,
+        filters: Filters,
+        metrics: Metrics,
     ) -> Result<Self> {
         let client = RpcClient::new(rpc_url.to_string());
-        log::debug!("Streamer: RpcClient created");
+        tracing::debug!("Streamer: RpcClient created");
         Ok(Self {
-            client: Arc::new(client),
-            block_config: Arc::new(block_config),
+            fetcher: Arc::new(Fetcher {
+                client,
+                block_config,
+                filters,
+                metrics,
+            }),
             slot_monitor,
             token,
+            concurrency: concurrency.max(1),
+            in_flight: FuturesOrdered::new(),
         })
     }
 
+    /// Fetches and parses the block of `slot`, within `fetch` and `parse` spans
     pub async fn fetch_block(&self, slot: Slot) -> Result<Block> {
-        let block = self
-            .client
-            .get_block_with_config(slot, *self.block_config)
-            .await
-            .map_err(|error| {
-                if let ClientErrorKind::RpcError(rpc_error) = error.kind() {
-                    if let RpcError::RpcResponseError { code, .. } = rpc_error {
-                        if code == &BLOCK_NOT_AVAILABLE {
-                            return Error::SlotMissing(slot);
-                        }
-                        if code == &SLOT_SKIPPED {
-                            return Error::SlotSkipped(slot);
-                        }
+        self.fetcher.fetch_block(slot).await
+    }
+
+    /// Starts fetching queued slots until `concurrency` blocks are in flight
+    /// Waits for a slot to be queued only when no block is in flight
+    async fn fill(&mut self) {
+        while self.in_flight.len() < self.concurrency {
+            // once cancelled, only the slots already queued are fetched
+            let slot = if self.token.is_cancelled() || !self.in_flight.is_empty() {
+                self.slot_monitor.try_recv()
+            } else {
+                tokio::select! {
+                    slot = self.slot_monitor.recv() => slot,
+                    _ = self.token.cancelled() => {
+                        tracing::info!("Draining {} queued slots", self.slot_monitor.len());
+                        self.slot_monitor.try_recv()
                     }
                 }
-                Error::RpcError(error)
-            });
+            };
+            let Some(QueuedSlot { slot, span }) = slot else {
+                break;
+            };
 
-        match block {
-            Ok(block) => Ok(Block::from(block)),
-            Err(e) => Err(e),
+            let fetcher = self.fetcher.clone();
+            self.in_flight.push_back(Box::pin(async move {
+                let block = fetcher.fetch_block(slot).instrument(span.clone()).await;
+                (block, span)
+            }));
         }
     }
 }
 
 impl BlockStream for Streamer {
     async fn next(&mut self) -> StreamerResult {
-        loop {
-            if self.token.is_cancelled() {
-                log::info!("TERMINATING");
-                return StreamerResult::Error(Error::Termination);
-            }
+        self.fill().await;
 
-            return match self.slot_monitor.recv().await {
-                Some(slot) => match self.fetch_block(slot).await {
-                    // TODO use mpmc channels
-                    // Consumer tasks will take the slot notifications, then fetch and parse the block
-                    // then pass block onto a multi-producer-multi-consumer queue for the Aggregators
-                    Ok(block) => StreamerResult::Block(block),
-                    Err(e) => StreamerResult::Error(e),
-                },
-                None => StreamerResult::EOS(),
-            };
+        match self.in_flight.next().await {
+            // TODO use mpmc channels
+            // Consumer tasks will take the slot notifications, then fetch and parse the block
+            // then pass block onto a multi-producer-multi-consumer queue for the Aggregators
+            Some((Ok(block), span)) => {
+                span.record("height", block.height);
+                span.record("transfers", block.transactions.len());
+                StreamerResult::Block(block, span)
+            }
+            Some((Err(e), span)) => {
+                span.in_scope(|| tracing::warn!("Fetching block failed: {}", e));
+                StreamerResult::Error(e)
+            }
+            None => {
+                let backfill = self.slot_monitor.backfill();
+                if self.token.is_cancelled() && !backfill.is_empty() {
+                    tracing::warn!(
+                        "{} dropped slots weren't backfilled, from {} to {}",
+                        backfill.len(),
+                        backfill[0],
+                        backfill[backfill.len() - 1]
+                    );
+                }
+                StreamerResult::EOS()
+            }
         }
     }
 }
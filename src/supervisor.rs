@@ -0,0 +1,276 @@
+use crate::error::*;
+use crate::health::{Health, TaskGuard, TaskState};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use utoipa::ToSchema;
+use warp::{Filter, Rejection, Reply};
+
+/// When and how often failed tasks are restarted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Consecutive failures restarted before shutting everything down
+    pub max_restarts: u32,
+    /// Wait before the first restart, doubled on each consecutive failure
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// A task running this long before failing is restarted as if it never failed before
+    pub reset_after: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            reset_after: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Wait before restarting after the `failures`th consecutive failure
+    fn backoff(&self, failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Runs the aggregator's tasks, restarting them when they fail
+/// A task failing more than [RestartPolicy::max_restarts] times in a row cancels the token,
+/// shutting every other task down. Task states are reported through [Health]
+pub struct Supervisor {
+    health: Health,
+    token: CancellationToken,
+    policy: RestartPolicy,
+    tasks: Vec<JoinHandle<Result<()>>>,
+}
+
+impl Supervisor {
+    pub fn new(health: Health, token: CancellationToken, policy: RestartPolicy) -> Self {
+        Self {
+            health,
+            token,
+            policy,
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Spawns a supervised task, `start` is called for every run of it
+    /// A run returning `Ok` or [Error::Termination] stops the task, any other error or a panic
+    /// gets it restarted
+    pub fn spawn<F, Fut>(&mut self, name: &str, start: F)
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let guard = self.health.task(name);
+        let task = supervise(
+            name.to_string(),
+            guard,
+            start,
+            self.policy,
+            self.token.clone(),
+        );
+        self.tasks.push(tokio::spawn(task));
+    }
+
+    /// Waits for every task to stop
+    /// Returns the error of the first task that failed for good
+    pub async fn join(self) -> Result<()> {
+        let mut result = Ok(());
+        for task in self.tasks {
+            let outcome = task
+                .await
+                .map_err(|error| Error::JoinError(error.to_string()))
+                .and_then(|outcome| outcome);
+            if let (Ok(()), Err(error)) = (&result, outcome) {
+                result = Err(error);
+            }
+        }
+        result
+    }
+}
+
+async fn supervise<F, Fut>(
+    name: String,
+    guard: TaskGuard,
+    mut start: F,
+    policy: RestartPolicy,
+    token: CancellationToken,
+) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let mut failures = 0;
+    loop {
+        let started = Instant::now();
+        // runs are spawned so a panic fails the run rather than the supervisor
+        let error = match tokio::spawn(start()).await {
+            Ok(Ok(())) | Ok(Err(Error::Termination)) => {
+                tracing::info!("{} stopped", name);
+                return Ok(());
+            }
+            Ok(Err(error)) => error,
+            Err(error) => Error::JoinError(error.to_string()),
+        };
+        // failing while shutting down isn't worth a restart
+        if token.is_cancelled() {
+            tracing::warn!("{} failed while shutting down: {}", name, error);
+            return Ok(());
+        }
+
+        if started.elapsed() >= policy.reset_after {
+            failures = 0;
+        }
+        failures += 1;
+        if failures > policy.max_restarts {
+            tracing::error!(
+                "{} failed {} times in a row, shutting down: {}",
+                name,
+                failures,
+                error
+            );
+            guard.failed(error.to_string());
+            token.cancel();
+            return Err(error);
+        }
+
+        let backoff = policy.backoff(failures);
+        tracing::warn!("{} failed, restarting in {:?}: {}", name, backoff, error);
+        guard.restarting(error.to_string());
+        tokio::select! {
+            _ = token.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(backoff) => {}
+        }
+        guard.running();
+    }
+}
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct StatusResponse {
+    /// State of each supervised task by name
+    pub tasks: BTreeMap<String, TaskState>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/status",
+    tag = "health",
+    responses(
+        (status = 200, description = "State of the supervised tasks", body = StatusResponse),
+    )
+)]
+async fn get_status(health: Health) -> std::result::Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&StatusResponse {
+        tasks: health.tasks(),
+    }))
+}
+
+/// - /status - state of every supervised task, with it's restarts and last error
+pub fn routes(health: Health) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("status")
+        .and(warp::get())
+        .and(warp::any().map(move || health.clone()))
+        .and_then(get_status)
+}
+
+#[cfg(test)]
+mod supervisor_tests {
+    use crate::api;
+    use crate::health::TaskStatus;
+    use crate::supervisor::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    fn policy() -> RestartPolicy {
+        RestartPolicy {
+            max_restarts: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            reset_after: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = RestartPolicy::default();
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(10), Duration::from_secs(60));
+        assert_eq!(policy.backoff(100), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn restart_and_escalate() {
+        let health = Health::default();
+        let token = CancellationToken::new();
+        let mut supervisor = Supervisor::new(health.clone(), token.clone(), policy());
+
+        // fails twice, then runs until shut down
+        let runs = Arc::new(AtomicU32::new(0));
+        let runs_move = runs.clone();
+        let token_move = token.clone();
+        supervisor.spawn("flaky", move || {
+            let run = runs_move.fetch_add(1, Ordering::SeqCst);
+            let token = token_move.clone();
+            async move {
+                match run {
+                    0 => Err(Error::StorageError(String::from("flaky"))),
+                    1 => panic!("flaky"),
+                    _ => {
+                        token.cancelled().await;
+                        Err(Error::Termination)
+                    }
+                }
+            }
+        });
+        while runs.load(Ordering::SeqCst) < 3 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let state = &health.tasks()["flaky"];
+        assert_eq!(state.status, TaskStatus::Running);
+        assert_eq!(state.restarts, 2);
+        assert!(!token.is_cancelled());
+
+        // fails every time, shutting the flaky task down with it
+        supervisor.spawn("broken", || async {
+            Err(Error::InvalidInput(String::from("broken")))
+        });
+        assert!(matches!(
+            supervisor.join().await,
+            Err(Error::InvalidInput(_))
+        ));
+        assert!(token.is_cancelled());
+        let tasks = health.tasks();
+        assert_eq!(tasks["broken"].status, TaskStatus::Failed);
+        assert_eq!(tasks["broken"].restarts, 2);
+        assert_eq!(
+            tasks["broken"].last_error.as_deref(),
+            Some("Invalid Input: broken")
+        );
+        assert_eq!(tasks["flaky"].status, TaskStatus::Stopped);
+    }
+
+    #[tokio::test]
+    async fn status() {
+        let health = Health::default();
+        let mut supervisor = Supervisor::new(health.clone(), CancellationToken::new(), policy());
+        supervisor.spawn("done", || async { Ok(()) });
+        supervisor.join().await.unwrap();
+
+        let routes = api::TestRoutes::default().health(health).build();
+        let response = warp::test::request().path("/status").reply(&routes).await;
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["tasks"]["done"]["status"], "stopped");
+        assert_eq!(body["tasks"]["done"]["restarts"], 0);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::error::*;
+use clap::ValueEnum;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use serde::Deserialize;
+use solana_program::clock::Slot;
+use tracing::field::Empty;
+use tracing::Span;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer, Registry};
+
+/// Name spans are exported under
+const SERVICE_NAME: &str = "solana-data-aggregator";
+
+/// Filter used when `RUST_LOG` isn't set
+const DEFAULT_FILTER: &str = "info";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One JSON object per line, with the fields of the enclosing spans
+    #[default]
+    Json,
+    /// Human readable lines
+    Text,
+}
+
+/// Span of a slot from it's notification until it's block is stored
+/// `height` and `transfers` are recorded once the block is fetched
+pub fn slot_span(slot: Slot) -> Span {
+    tracing::info_span!(parent: None, "slot", slot, height = Empty, transfers = Empty)
+}
+
+/// Formats events onto `writer`
+fn fmt_layer<W>(format: LogFormat, writer: W) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    match format {
+        LogFormat::Json => layer
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+        LogFormat::Text => layer.boxed(),
+    }
+}
+
+/// Flushes exported spans once dropped, keep it alive until exiting
+/// Drop it outside of the async runtime, the exporter blocks while flushing
+pub struct TelemetryGuard(Option<SdkTracerProvider>);
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.0.take() {
+            if let Err(error) = provider.shutdown() {
+                eprintln!("Failed to flush spans: {}", error);
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber, logging onto `writer` in `format` filtered by `RUST_LOG`
+/// Spans are exported to the OTLP/HTTP collector at `otlp_endpoint` if given,
+/// e.g. `http://localhost:4318/v1/traces`. Records of the `log` crate are captured as well
+pub fn init<W>(format: LogFormat, otlp_endpoint: Option<&str>, writer: W) -> Result<TelemetryGuard>
+where
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+
+    let provider = match otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = SpanExporter::builder()
+                .with_http()
+                .with_endpoint(endpoint)
+                .build()
+                .map_err(|e| Error::InvalidInput(format!("OTLP exporter: {}", e)))?;
+            let resource = Resource::builder().with_service_name(SERVICE_NAME).build();
+            Some(
+                SdkTracerProvider::builder()
+                    .with_batch_exporter(exporter)
+                    .with_resource(resource)
+                    .build(),
+            )
+        }
+        None => None,
+    };
+    let otel_layer = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME)));
+
+    tracing_subscriber::registry()
+        .with(fmt_layer(format, writer))
+        .with(otel_layer)
+        .with(filter)
+        .try_init()
+        .map_err(|e| Error::InvalidInput(format!("Tracing subscriber: {}", e)))?;
+    Ok(TelemetryGuard(provider))
+}
+
+#[cfg(test)]
+mod telemetry_tests {
+    use crate::aggregator::{Aggregator, QueuedBlocks};
+    use crate::feed;
+    use crate::metrics::Metrics;
+    use crate::storage::InMemoryStorage;
+    use crate::telemetry::*;
+    use crate::types::{Block, Transaction};
+    use crate::webhooks::Webhooks;
+    use solana_program::pubkey::Pubkey;
+    use std::collections::VecDeque;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tokio_util::sync::CancellationToken;
+
+    /// Collects formatted output
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'writer> MakeWriter<'writer> for Output {
+        type Writer = Output;
+
+        fn make_writer(&'writer self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    /// Events recorded while storing a block carry the fields of it's slot
+    #[tokio::test]
+    async fn slot_fields() {
+        let output = Output::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(fmt_layer(LogFormat::Json, output.clone()))
+            .with(EnvFilter::new("info"));
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let transaction = Transaction {
+            source: Pubkey::new_unique().into(),
+            destination: Pubkey::new_unique().into(),
+            amount: 10,
+        };
+        let block = Block {
+            height: 7,
+            slot: 107,
+            hash: String::from("block_hash"),
+            timestamp: 7,
+            transactions: vec![transaction; 2],
+        };
+        let token = CancellationToken::new();
+        token.cancel();
+        let mut aggregator = Aggregator::new(
+            QueuedBlocks(VecDeque::from([block])),
+            token,
+            InMemoryStorage::new(),
+            feed::block_feed(),
+            Metrics::new(),
+            Webhooks::disabled(),
+        );
+        let _ = aggregator.run().await;
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let event: serde_json::Value = output
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .find(|event| event["fields"]["message"] == "Recording block")
+            .expect("block recorded");
+        assert_eq!(event["span"]["name"], "store");
+        let slot = &event["spans"][0];
+        assert_eq!(slot["name"], "slot");
+        assert_eq!(slot["slot"], 107);
+        assert_eq!(slot["height"], 7);
+        assert_eq!(slot["transfers"], 2);
+    }
+}
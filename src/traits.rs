@@ -22,8 +22,50 @@ pub trait Storage {
     /// Processes an action received from the [ActionsQueueRx]
     // async fn process_action(&self, action: Action) -> Result<()>;
     async fn add_block(&mut self, block: &Block) -> Result<()>;
-    async fn get_transactions(&self, address: &Address) -> Result<Vec<Transaction>>;
+    async fn get_block(&self, height: u64) -> Result<Block>;
+    /// Returns the stored block with the greatest height
+    async fn get_latest_block(&self) -> Result<Block>;
+    async fn get_blocks(&self, query: &BlockQuery) -> Result<BlockPage>;
+    /// Returns a page of transactions made by `address`, see [TransactionQuery]
+    async fn get_transactions(
+        &self,
+        address: &Address,
+        query: &TransactionQuery,
+    ) -> Result<TransactionPage>;
+    /// Returns a page of transactions for each of `addresses`, in the same order
+    /// Addresses without transactions get an empty page rather than an error
+    async fn get_transactions_batch(
+        &self,
+        addresses: &[Address],
+        query: &TransactionQuery,
+    ) -> Result<Vec<TransactionPage>>;
     async fn get_account(&self, address: &Address) -> Result<Account>;
+    /// Returns the account of each of `addresses`, in the same order, `None` for those not stored
+    async fn get_accounts(&self, addresses: &[Address]) -> Result<Vec<Option<Account>>>;
+    /// Returns the account as it was at a past slot or block time
+    async fn get_account_at(&self, address: &Address, at: At) -> Result<Account>;
+    /// Returns the account's balance and flows bucketed by [SeriesQuery::interval]
+    /// Buckets without activity are included, carrying the previous balance
+    async fn get_balance_series(
+        &self,
+        address: &Address,
+        query: &SeriesQuery,
+    ) -> Result<Vec<BalanceBucket>>;
+    /// Stores an API key, replacing any with the same [ApiKey::key]
+    async fn put_api_key(&mut self, key: &ApiKey) -> Result<()>;
+    async fn remove_api_key(&mut self, key: &str) -> Result<()>;
+    async fn get_api_keys(&self) -> Result<Vec<ApiKey>>;
+    /// Stores a watched address, replacing any watch of the same [Watch::address]
+    async fn put_watch(&mut self, watch: &Watch) -> Result<()>;
+    async fn remove_watch(&mut self, address: &Address) -> Result<()>;
+    async fn get_watches(&self) -> Result<Vec<Watch>>;
+    /// Persists everything stored so far, recording the latest stored block's slot and height
+    /// Called once ingestion has stopped, before exiting
+    async fn flush(&mut self) -> Result<()>;
+    /// Whether every write is refused, as storage is written by another process
+    fn is_read_only(&self) -> bool {
+        false
+    }
 }
 
 // /// Abstraction over the [Storage] trait for the [Aggregator]
@@ -1,12 +1,82 @@
-/// TODO could create interface traits for Transaction, Block and Account types
-/// So that we could enforce what information is required for each type to contain
-
+//! TODO could create interface traits for Transaction, Block and Account types
+//! So that we could enforce what information is required for each type to contain
 
 use crate::error::Error;
-use serde::{Deserialize, Serialize};
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use solana_program::clock::Slot;
+use solana_program::pubkey::Pubkey;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use tracing::Span;
+use utoipa::openapi::schema::{ObjectBuilder, Schema, Type};
+use utoipa::openapi::RefOr;
+use utoipa::{PartialSchema, ToSchema};
+
+/// OpenAPI schema of a type serialized as a string
+fn string_schema(description: &str, example: &str) -> RefOr<Schema> {
+    ObjectBuilder::new()
+        .schema_type(Type::String)
+        .description(Some(description))
+        .examples([example])
+        .into()
+}
 
 pub type Hash = String;
-pub type Address = String;
+
+/// Solana account address, a 32 byte public key
+/// Represented as a base58 string when serialized, as in RPC responses
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Address(pub Pubkey);
+
+impl From<Pubkey> for Address {
+    fn from(pubkey: Pubkey) -> Self {
+        Self(pubkey)
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        address.parse().map(Self).map_err(|_| {
+            Error::InvalidInput(format!(
+                "Invalid address: {}, expected a base58 encoded 32 byte public key",
+                address
+            ))
+        })
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let address = String::deserialize(deserializer)?;
+        address.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl PartialSchema for Address {
+    fn schema() -> RefOr<Schema> {
+        string_schema(
+            "Base58 encoded 32 byte public key",
+            "tKeYE4wtowRb8yRroZShTipE18YVnqwXjsSAoNsFU6g",
+        )
+    }
+}
+
+impl ToSchema for Address {}
 
 // pub type ActionsQueueRx = mpsc::UnboundedReceiver<Action>;
 // pub type ActionsQueueTx = mpsc::UnboundedSender<Action>;
@@ -14,7 +84,8 @@ pub type Address = String;
 // pub type SlotMonitorTx = mpsc::UnboundedSender<Slot>;
 
 pub enum StreamerResult {
-    Block(Block),
+    /// A block along with the span of it's slot, see [crate::telemetry::slot_span]
+    Block(Block, Span),
     EOS(),
     Error(Error),
 }
@@ -31,7 +102,7 @@ pub enum StreamerResult {
 //     GetAccounts(ActionResult),
 // }
 
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default, ToSchema)]
 pub struct Transaction {
     pub source: Address,
     pub destination: Address,
@@ -42,20 +113,378 @@ pub struct Transaction {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Block {
     pub height: u64,
+    #[serde(default)]
+    pub slot: Slot,
     pub hash: Hash,
     pub timestamp: i64,
     pub transactions: Vec<Transaction>,
 }
 
+/// Block as served by the API, along with it's transfer count
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, ToSchema)]
+pub struct BlockInfo {
+    pub height: u64,
+    pub slot: Slot,
+    pub hash: Hash,
+    pub timestamp: i64,
+    pub transaction_count: usize,
+    pub transactions: Vec<Transaction>,
+}
+
+impl From<Block> for BlockInfo {
+    fn from(block: Block) -> Self {
+        Self {
+            height: block.height,
+            slot: block.slot,
+            hash: block.hash,
+            timestamp: block.timestamp,
+            transaction_count: block.transactions.len(),
+            transactions: block.transactions,
+        }
+    }
+}
+
+/// Compact description of a stored block, as streamed to clients
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, ToSchema)]
+pub struct BlockSummary {
+    pub height: u64,
+    pub slot: Slot,
+    pub hash: Hash,
+    pub timestamp: i64,
+    pub transaction_count: usize,
+}
+
+impl From<&Block> for BlockSummary {
+    fn from(block: &Block) -> Self {
+        Self {
+            height: block.height,
+            slot: block.slot,
+            hash: block.hash.clone(),
+            timestamp: block.timestamp,
+            transaction_count: block.transactions.len(),
+        }
+    }
+}
+
+/// Selects a page of stored blocks in ascending height, bounds are inclusive
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockQuery {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub limit: usize,
+}
+
+impl Default for BlockQuery {
+    fn default() -> Self {
+        Self {
+            from: None,
+            to: None,
+            limit: usize::MAX,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BlockPage {
+    pub blocks: Vec<Block>,
+    /// Height to continue from for the following page, `None` if this is the last one
+    pub next_height: Option<u64>,
+}
+
 /// TODO make Account type generic over the type of Address it contains
-#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default, ToSchema)]
 pub struct Account {
     pub address: Address,
     pub balance: i64,
 }
 
+/// Credential clients authenticate to the API with
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub struct ApiKey {
+    pub key: String,
+    /// Who the key was issued to
+    pub name: String,
+    /// Requests allowed per day, unlimited if `None`
+    #[serde(default)]
+    pub quota: Option<u64>,
+    /// Whether the key can issue and revoke keys
+    #[serde(default)]
+    pub admin: bool,
+}
+
+/// Address webhooks are sent for when it's transfers are stored, see [crate::webhooks]
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Watch {
+    pub address: Address,
+    /// Only transfers of at least this many lamports, every transfer if `None`
+    #[serde(default)]
+    pub min_amount: Option<u64>,
+}
+
+impl Watch {
+    pub fn matches(&self, transaction: &Transaction) -> bool {
+        transaction.amount >= self.min_amount.unwrap_or_default()
+            && (transaction.source == self.address || transaction.destination == self.address)
+    }
+}
+
+/// Balance of an account right after a block that touched it
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
+pub struct BalanceCheckpoint {
+    pub slot: Slot,
+    pub timestamp: i64,
+    pub balance: i64,
+}
+
+/// Bucket width of a balance series
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Deserialize,
+    Serialize,
+    Default,
+    ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Interval {
+    Hour,
+    #[default]
+    Day,
+}
+
+impl Interval {
+    /// Every interval a series is cached for
+    pub const ALL: [Interval; 2] = [Interval::Hour, Interval::Day];
+
+    pub fn seconds(&self) -> i64 {
+        match self {
+            Interval::Hour => 60 * 60,
+            Interval::Day => 24 * 60 * 60,
+        }
+    }
+
+    /// Start of the bucket `timestamp` falls in, saturating at the earliest representable time
+    pub fn bucket_start(&self, timestamp: i64) -> i64 {
+        timestamp
+            .div_euclid(self.seconds())
+            .saturating_mul(self.seconds())
+    }
+}
+
+impl Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interval::Hour => write!(f, "hour"),
+            Interval::Day => write!(f, "day"),
+        }
+    }
+}
+
+/// Account activity over one bucket of a balance series
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default, ToSchema)]
+pub struct BalanceBucket {
+    /// Bucket start, unix seconds
+    pub start: i64,
+    pub inflow: u64,
+    pub outflow: u64,
+    /// Balance at the close of the bucket
+    pub balance: i64,
+}
+
+/// Selects a balance series, time bounds are inclusive unix seconds
+/// Without bounds the series spans the account's recorded activity, up to it's latest
+/// [crate::storage::MAX_SERIES_BUCKETS] buckets
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct SeriesQuery {
+    pub interval: Interval,
+    pub from_time: Option<i64>,
+    pub to_time: Option<i64>,
+}
+
+/// Point in history to look an account up at
+/// Parsed from `slot:<slot>` or `time:<unix seconds>`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum At {
+    Slot(Slot),
+    Timestamp(i64),
+}
+
+impl At {
+    /// Whether `checkpoint` was recorded at or before this point
+    pub fn includes(&self, checkpoint: &BalanceCheckpoint) -> bool {
+        match self {
+            At::Slot(slot) => checkpoint.slot <= *slot,
+            At::Timestamp(timestamp) => checkpoint.timestamp <= *timestamp,
+        }
+    }
+}
+
+impl Display for At {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            At::Slot(slot) => write!(f, "slot:{}", slot),
+            At::Timestamp(timestamp) => write!(f, "time:{}", timestamp),
+        }
+    }
+}
+
+impl FromStr for At {
+    type Err = Error;
+
+    fn from_str(at: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            Error::InvalidInput(format!(
+                "Invalid point in time: {}, expected slot:<slot> or time:<unix seconds>",
+                at
+            ))
+        };
+        match at.split_once(':') {
+            Some(("slot", slot)) => slot.parse().map(At::Slot).map_err(|_| invalid()),
+            Some(("time", timestamp)) => {
+                timestamp.parse().map(At::Timestamp).map_err(|_| invalid())
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for At {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let at = String::deserialize(deserializer)?;
+        at.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl PartialSchema for At {
+    fn schema() -> RefOr<Schema> {
+        string_schema("`slot:<slot>` or `time:<unix seconds>`", "slot:312448212")
+    }
+}
+
+impl ToSchema for At {}
+
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default)]
 pub struct TransactionIndex {
     pub block_height: u64,
+    #[serde(default)]
+    pub slot: Slot,
+    /// Block time, lets range queries skip reading blocks
+    #[serde(default)]
+    pub timestamp: i64,
     pub index: usize,
 }
+
+impl TransactionIndex {
+    /// Position of this transaction in the chain, index entries are kept sorted by it
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            slot: self.slot,
+            index: self.index,
+        }
+    }
+}
+
+/// Opaque pagination cursor, points at the last transaction of a page
+/// Encoded as hex so clients don't rely on it's internals
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cursor {
+    pub slot: Slot,
+    pub index: usize,
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}{:08x}", self.slot, self.index)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = Error;
+
+    fn from_str(cursor: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidInput(format!("Invalid cursor: {}", cursor));
+        if cursor.len() != 24 || !cursor.is_ascii() {
+            return Err(invalid());
+        }
+        let slot = u64::from_str_radix(&cursor[..16], 16).map_err(|_| invalid())?;
+        let index = usize::from_str_radix(&cursor[16..], 16).map_err(|_| invalid())?;
+        Ok(Self { slot, index })
+    }
+}
+
+impl Serialize for Cursor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cursor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let cursor = String::deserialize(deserializer)?;
+        cursor.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl PartialSchema for Cursor {
+    fn schema() -> RefOr<Schema> {
+        string_schema("Opaque pagination cursor", "0000000012a5f1d400000003")
+    }
+}
+
+impl ToSchema for Cursor {}
+
+/// Order in which paginated results are returned
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, Default, ToSchema, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    /// Newest first
+    #[default]
+    Desc,
+    /// Oldest first
+    Asc,
+}
+
+/// Selects a page of an address's transaction history
+/// Slot and block time bounds are inclusive, block time is in unix seconds
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransactionQuery {
+    pub limit: usize,
+    /// Page starts right after this cursor, from the start of history if `None`
+    pub cursor: Option<Cursor>,
+    pub order: Order,
+    pub from_slot: Option<Slot>,
+    pub to_slot: Option<Slot>,
+    pub from_time: Option<i64>,
+    pub to_time: Option<i64>,
+}
+
+impl Default for TransactionQuery {
+    fn default() -> Self {
+        Self {
+            limit: usize::MAX,
+            cursor: None,
+            order: Order::default(),
+            from_slot: None,
+            to_slot: None,
+            from_time: None,
+            to_time: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, Default, ToSchema)]
+pub struct TransactionPage {
+    pub transactions: Vec<Transaction>,
+    /// Position of each transaction, only kept for edges of the GraphQL connections
+    #[serde(skip)]
+    pub cursors: Vec<Cursor>,
+    /// Cursor for the following page, `None` if this is the last one
+    pub next_cursor: Option<Cursor>,
+}
@@ -0,0 +1,230 @@
+use crate::error::*;
+use crate::traits::Storage;
+use crate::types::{Address, At, BlockQuery, TransactionQuery};
+use serde::Serialize;
+use solana_program::clock::Slot;
+use std::collections::BTreeMap;
+
+/// Blocks read from storage at once
+const PAGE_SIZE: usize = 1000;
+
+/// Outcome of [verify]
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Report {
+    pub blocks: usize,
+    pub transfers: usize,
+    pub accounts: usize,
+    /// Inconsistencies found, storage is sound if there are none
+    pub issues: Vec<String>,
+}
+
+/// Checks the integrity of storage by replaying every stored block
+/// - block slots and times don't decrease with height
+/// - the latest block is the one with the greatest height
+/// - balances, past balances and transaction indexes match the transfers of the stored blocks
+pub async fn verify<S: Storage>(storage: &S) -> Result<Report> {
+    let mut report = Report::default();
+    let mut balances: BTreeMap<Address, i64> = BTreeMap::new();
+    let mut sent: BTreeMap<Address, usize> = BTreeMap::new();
+    let mut previous: Option<(u64, Slot, i64)> = None;
+
+    let mut query = BlockQuery {
+        limit: PAGE_SIZE,
+        ..BlockQuery::default()
+    };
+    loop {
+        let page = match storage.get_blocks(&query).await {
+            Ok(page) => page,
+            // a height is listed without its block, the following ones can't be read
+            Err(error) => {
+                report.issues.push(error.to_string());
+                break;
+            }
+        };
+        for block in page.blocks {
+            if let Some((height, slot, timestamp)) = previous {
+                if block.height <= height {
+                    report.issues.push(format!(
+                        "Block {} is listed after block {}",
+                        block.height, height
+                    ));
+                }
+                if block.slot <= slot {
+                    report.issues.push(format!(
+                        "Block {} at slot {} doesn't follow block {} at slot {}",
+                        block.height, block.slot, height, slot
+                    ));
+                }
+                if block.timestamp < timestamp {
+                    report.issues.push(format!(
+                        "Block {} at time {} precedes block {} at time {}",
+                        block.height, block.timestamp, height, timestamp
+                    ));
+                }
+            }
+            previous = Some((block.height, block.slot, block.timestamp));
+
+            report.blocks += 1;
+            report.transfers += block.transactions.len();
+            for transaction in &block.transactions {
+                *balances.entry(transaction.source).or_default() -= transaction.amount as i64;
+                *balances.entry(transaction.destination).or_default() += transaction.amount as i64;
+                *sent.entry(transaction.source).or_default() += 1;
+            }
+        }
+        match page.next_height {
+            Some(height) => query.from = Some(height),
+            None => break,
+        }
+    }
+
+    match (storage.get_latest_block().await, previous) {
+        (Ok(latest), Some((height, _, _))) if latest.height != height => {
+            report.issues.push(format!(
+                "Latest block is {} while the greatest stored height is {}",
+                latest.height, height
+            ))
+        }
+        (Ok(_), Some(_)) | (Err(Error::NotFound(_)), None) => {}
+        (Ok(latest), None) => report.issues.push(format!(
+            "Latest block is {} but no block is listed",
+            latest.height
+        )),
+        (Err(error), _) => report
+            .issues
+            .push(format!("Latest block can't be read: {}", error)),
+    }
+
+    report.accounts = balances.len();
+    for (address, expected) in &balances {
+        match storage.get_account(address).await {
+            Ok(account) if account.balance != *expected => report.issues.push(format!(
+                "Account {} has a balance of {}, its transfers add up to {}",
+                address, account.balance, expected
+            )),
+            Ok(_) => {}
+            Err(error) => report.issues.push(error.to_string()),
+        }
+        match storage.get_account_at(address, At::Slot(Slot::MAX)).await {
+            Ok(account) if account.balance != *expected => report.issues.push(format!(
+                "Account {} has a balance history ending at {}, its transfers add up to {}",
+                address, account.balance, expected
+            )),
+            Ok(_) => {}
+            Err(error) => report.issues.push(error.to_string()),
+        }
+    }
+    for (address, expected) in &sent {
+        match storage
+            .get_transactions(address, &TransactionQuery::default())
+            .await
+        {
+            Ok(page) if page.transactions.len() != *expected => report.issues.push(format!(
+                "Account {} has {} indexed transactions, it sent {}",
+                address,
+                page.transactions.len(),
+                expected
+            )),
+            Ok(_) => {}
+            Err(error) => report.issues.push(error.to_string()),
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod verify_tests {
+    use crate::storage::{Database, InMemoryStorage};
+    use crate::types::{Block, Transaction};
+    use crate::verify::*;
+    use rand::Rng;
+    use solana_program::pubkey::Pubkey;
+
+    fn blocks() -> Vec<Block> {
+        let source: Address = Pubkey::new_unique().into();
+        let destination: Address = Pubkey::new_unique().into();
+        (1..=3)
+            .map(|height| Block {
+                height,
+                slot: height + 100,
+                hash: format!("block_hash{}", height),
+                timestamp: height as i64,
+                transactions: vec![Transaction {
+                    source,
+                    destination,
+                    amount: height * 10,
+                }],
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn sound() {
+        let mut storage = InMemoryStorage::new();
+        let report = verify(&storage).await.unwrap();
+        assert_eq!(report.blocks, 0);
+        assert!(report.issues.is_empty());
+
+        for block in blocks() {
+            storage.add_block(&block).await.unwrap();
+        }
+        let report = verify(&storage).await.unwrap();
+        assert_eq!(report.blocks, 3);
+        assert_eq!(report.transfers, 3);
+        assert_eq!(report.accounts, 2);
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+    }
+
+    #[tokio::test]
+    async fn out_of_order() {
+        let mut storage = InMemoryStorage::new();
+        let mut blocks = blocks();
+        blocks[2].slot = 50;
+        for block in blocks {
+            storage.add_block(&block).await.unwrap();
+        }
+        let report = verify(&storage).await.unwrap();
+        assert_eq!(
+            report.issues,
+            vec!["Block 3 at slot 50 doesn't follow block 2 at slot 102"]
+        );
+    }
+
+    #[tokio::test]
+    async fn tampered() {
+        let path =
+            std::env::temp_dir().join(format!("verify-{}.json", rand::thread_rng().gen::<u64>()));
+        let mut db = Database::new(path.to_str().unwrap()).unwrap();
+        for block in blocks() {
+            db.add_block(&block).await.unwrap();
+        }
+        db.flush().await.unwrap();
+
+        // edit a balance behind the database's back
+        let mut data: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let (key, balance) = data
+            .as_object_mut()
+            .unwrap()
+            .iter_mut()
+            .find(|(key, _)| key.starts_with("AccountBalance-"))
+            .unwrap();
+        let address = key.trim_start_matches("AccountBalance-").to_string();
+        let expected = balance.as_i64().unwrap();
+        *balance = serde_json::Value::from(expected + 1);
+        std::fs::write(&path, data.to_string()).unwrap();
+
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        let report = verify(&db).await.unwrap();
+        assert_eq!(
+            report.issues,
+            vec![format!(
+                "Account {} has a balance of {}, its transfers add up to {}",
+                address,
+                expected + 1,
+                expected
+            )]
+        );
+        crate::storage::remove_database(path);
+    }
+}
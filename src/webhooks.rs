@@ -0,0 +1,858 @@
+use crate::api::{ApiError, ErrorBody};
+use crate::auth::{authorize_admin, writable, ApiKeys};
+use crate::config::WebhookConfig;
+use crate::error::*;
+use crate::traits::Storage;
+use crate::types::{Address, Block, Transaction, Watch};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use solana_program::clock::Slot;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::Instrument;
+use utoipa::ToSchema;
+use warp::http::StatusCode;
+use warp::{Filter, Rejection, Reply};
+
+/// Header carrying `sha256=<hex HMAC-SHA256 of "<timestamp>.<body>">`, keyed with the webhook secret
+pub const SIGNATURE_HEADER: &str = "x-aggregator-signature";
+/// Header carrying the unix seconds an attempt was signed at, covered by the signature
+/// Receivers are expected to refuse webhooks signed more than 5 minutes away from their clock
+pub const TIMESTAMP_HEADER: &str = "x-aggregator-timestamp";
+/// Header carrying [WebhookEvent::id], the same on every attempt
+pub const DELIVERY_HEADER: &str = "x-aggregator-delivery";
+/// Deliveries kept in the log, the oldest are dropped first
+pub const DELIVERY_LOG_CAPACITY: usize = 1000;
+
+/// How often and how long a webhook is attempted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeliveryPolicy {
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled on each retry
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Time an attempt can take
+    pub timeout: Duration,
+}
+
+impl Default for DeliveryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl From<&WebhookConfig> for DeliveryPolicy {
+    fn from(config: &WebhookConfig) -> Self {
+        Self {
+            max_attempts: config.max_attempts,
+            initial_backoff: Duration::from_secs(config.backoff),
+            timeout: Duration::from_secs(config.timeout),
+            ..Self::default()
+        }
+    }
+}
+
+impl DeliveryPolicy {
+    /// Wait before retrying after the `attempts`th failed attempt
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Body of a webhook, the transfers of a stored block matching the watchlist
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, ToSchema)]
+pub struct WebhookEvent {
+    /// Hash of the block, receivers can drop events they already handled
+    pub id: String,
+    pub height: u64,
+    pub slot: Slot,
+    pub timestamp: i64,
+    pub transactions: Vec<Transaction>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryState {
+    /// Being attempted, or waiting to be retried
+    Pending,
+    Delivered,
+    /// Given up on
+    Failed,
+}
+
+/// Entry of the delivery log, updated after every attempt
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize, ToSchema)]
+pub struct Delivery {
+    /// [WebhookEvent::id] of the webhook
+    pub id: String,
+    pub height: u64,
+    /// Transfers the webhook carries
+    pub transactions: usize,
+    pub state: DeliveryState,
+    pub attempts: u32,
+    /// Status code of the last response, `None` if the endpoint wasn't reached
+    pub status: Option<u16>,
+    /// Why the last attempt failed
+    pub error: Option<String>,
+    /// Unix time of the last attempt
+    pub attempted_at: Option<i64>,
+}
+
+/// Where webhooks are sent
+struct Endpoint {
+    url: String,
+    secret: String,
+    client: reqwest::Client,
+    policy: DeliveryPolicy,
+}
+
+/// Watchlist and delivery log, shared by the aggregator and the API
+/// Without an endpoint the watchlist can still be managed, but no webhook is sent
+/// The log is kept in memory, deliveries still retrying on exit are abandoned
+#[derive(Clone)]
+pub struct Webhooks {
+    endpoint: Option<Arc<Endpoint>>,
+    watches: Arc<RwLock<BTreeMap<Address, Watch>>>,
+    /// Addresses listed in the config file, only the file can stop watching them
+    listed: Arc<BTreeSet<Address>>,
+    log: Arc<Mutex<VecDeque<Delivery>>>,
+}
+
+impl Webhooks {
+    /// Sends no webhook, for deployments without an endpoint
+    pub fn disabled() -> Self {
+        Self {
+            endpoint: None,
+            watches: Arc::default(),
+            listed: Arc::default(),
+            log: Arc::default(),
+        }
+    }
+
+    /// POSTs webhooks for transfers of `watchlist` to `url`, signed with `secret`
+    pub fn new(
+        url: &str,
+        secret: &str,
+        policy: DeliveryPolicy,
+        watchlist: impl IntoIterator<Item = Watch>,
+    ) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(policy.timeout)
+            .build()
+            .map_err(|e| Error::InvalidInput(format!("Webhook client: {}", e)))?;
+        let webhooks = Self {
+            endpoint: Some(Arc::new(Endpoint {
+                url: url.to_string(),
+                secret: secret.to_string(),
+                client,
+                policy,
+            })),
+            ..Self::disabled()
+        };
+        for watch in watchlist {
+            webhooks.watch(watch);
+        }
+        Ok(webhooks)
+    }
+
+    /// Webhooks set up by `config`, watching the addresses it lists along with those stored
+    /// Stored watches replace listed ones of the same address
+    pub async fn load<S: Storage>(config: &WebhookConfig, storage: &S) -> Result<Self> {
+        let mut watchlist = config.watchlist.clone();
+        watchlist.extend(storage.get_watches().await?);
+        let webhooks = match (&config.url, &config.secret) {
+            (Some(url), Some(secret)) => Self::new(
+                url.expose(),
+                secret.expose(),
+                DeliveryPolicy::from(config),
+                watchlist,
+            )?,
+            _ => {
+                let webhooks = Self::disabled();
+                for watch in watchlist {
+                    webhooks.watch(watch);
+                }
+                webhooks
+            }
+        };
+        let listed = config.watchlist.iter().map(|watch| watch.address);
+        Ok(Self {
+            listed: Arc::new(listed.collect()),
+            ..webhooks
+        })
+    }
+
+    /// Replaces the watchlist with the addresses `config` lists along with those stored
+    /// Lets replicas follow the watchlist managed by the process writing storage
+    pub async fn reload<S: Storage>(&self, config: &WebhookConfig, storage: &S) -> Result<()> {
+        let mut watchlist = config.watchlist.clone();
+        watchlist.extend(storage.get_watches().await?);
+        let watches = watchlist
+            .into_iter()
+            .map(|watch| (watch.address, watch))
+            .collect();
+        *self.watches.write().unwrap() = watches;
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.endpoint.is_some()
+    }
+
+    /// Watches an address, replacing any watch of it added through the API
+    pub fn watch(&self, watch: Watch) {
+        self.watches.write().unwrap().insert(watch.address, watch);
+    }
+
+    /// Whether the address is listed in the config file, rather than watched through the API
+    pub fn is_listed(&self, address: &Address) -> bool {
+        self.listed.contains(address)
+    }
+
+    /// Returns whether the address was watched
+    pub fn unwatch(&self, address: &Address) -> bool {
+        self.watches.write().unwrap().remove(address).is_some()
+    }
+
+    pub fn watchlist(&self) -> Vec<Watch> {
+        self.watches.read().unwrap().values().cloned().collect()
+    }
+
+    /// Latest deliveries, newest first
+    pub fn deliveries(&self) -> Vec<Delivery> {
+        self.log.lock().unwrap().iter().rev().cloned().collect()
+    }
+
+    /// Webhook for the transfers of `block` matching the watchlist, `None` if there are none
+    pub fn event(&self, block: &Block) -> Option<WebhookEvent> {
+        let watches = self.watches.read().unwrap();
+        let watched = |address: &Address, transaction: &Transaction| {
+            watches
+                .get(address)
+                .is_some_and(|watch| watch.matches(transaction))
+        };
+        let transactions: Vec<Transaction> = block
+            .transactions
+            .iter()
+            .filter(|transaction| {
+                watched(&transaction.source, transaction)
+                    || watched(&transaction.destination, transaction)
+            })
+            .cloned()
+            .collect();
+        if transactions.is_empty() {
+            return None;
+        }
+        Some(WebhookEvent {
+            id: block.hash.clone(),
+            height: block.height,
+            slot: block.slot,
+            timestamp: block.timestamp,
+            transactions,
+        })
+    }
+
+    /// Sends a webhook for `block` in the background, if it has transfers matching the watchlist
+    pub fn notify(&self, block: &Block) {
+        let Some(endpoint) = &self.endpoint else {
+            return;
+        };
+        let Some(event) = self.event(block) else {
+            return;
+        };
+        let span = tracing::info_span!("webhook", id = %event.id);
+        tokio::spawn(
+            self.clone()
+                .deliver(endpoint.clone(), event)
+                .instrument(span),
+        );
+    }
+
+    /// Attempts `event` until it's delivered, it's refused or [DeliveryPolicy::max_attempts] is reached
+    /// Network errors, timeouts, 408, 429 and 5xx responses are retried
+    async fn deliver(self, endpoint: Arc<Endpoint>, event: WebhookEvent) {
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(error) => {
+                tracing::error!("Failed to serialize webhook: {}", error);
+                return;
+            }
+        };
+        let mut delivery = Delivery {
+            id: event.id.clone(),
+            height: event.height,
+            transactions: event.transactions.len(),
+            state: DeliveryState::Pending,
+            attempts: 0,
+            status: None,
+            error: None,
+            attempted_at: None,
+        };
+
+        loop {
+            delivery.attempts += 1;
+            // signed afresh on every attempt, retries would otherwise fall out of tolerance
+            let timestamp = unix_now();
+            delivery.attempted_at = Some(timestamp);
+            let response = endpoint
+                .client
+                .post(&endpoint.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .header(SIGNATURE_HEADER, sign(&endpoint.secret, timestamp, &body))
+                .header(TIMESTAMP_HEADER, timestamp)
+                .header(DELIVERY_HEADER, &event.id)
+                .body(body.clone())
+                .send()
+                .await;
+            let retry = match response {
+                Ok(response) if response.status().is_success() => {
+                    delivery.state = DeliveryState::Delivered;
+                    delivery.status = Some(response.status().as_u16());
+                    delivery.error = None;
+                    self.record(&delivery);
+                    tracing::info!(attempts = delivery.attempts, "Webhook delivered");
+                    return;
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    delivery.status = Some(status.as_u16());
+                    delivery.error = Some(format!("Endpoint responded {}", status));
+                    status.is_server_error()
+                        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                        || status == reqwest::StatusCode::REQUEST_TIMEOUT
+                }
+                Err(error) => {
+                    delivery.status = None;
+                    delivery.error = Some(describe(error));
+                    true
+                }
+            };
+            let error = delivery.error.clone().unwrap_or_default();
+
+            if !retry || delivery.attempts >= endpoint.policy.max_attempts {
+                delivery.state = DeliveryState::Failed;
+                self.record(&delivery);
+                tracing::error!(
+                    attempts = delivery.attempts,
+                    "Webhook delivery failed for good: {}",
+                    error
+                );
+                return;
+            }
+            self.record(&delivery);
+            let backoff = endpoint.policy.backoff(delivery.attempts);
+            tracing::warn!(
+                attempts = delivery.attempts,
+                "Webhook delivery failed, retrying in {:?}: {}",
+                backoff,
+                error
+            );
+            tokio::time::sleep(backoff).await;
+        }
+    }
+
+    /// Adds `delivery` to the log, replacing the entry of an earlier attempt
+    fn record(&self, delivery: &Delivery) {
+        let mut log = self.log.lock().unwrap();
+        match log.iter_mut().rev().find(|entry| entry.id == delivery.id) {
+            Some(entry) => *entry = delivery.clone(),
+            None => {
+                if log.len() >= DELIVERY_LOG_CAPACITY {
+                    log.pop_front();
+                }
+                log.push_back(delivery.clone());
+            }
+        }
+    }
+}
+
+/// Value of [SIGNATURE_HEADER] for `body` sent at `timestamp`, receivers recompute it to
+/// authenticate webhooks, checking [TIMESTAMP_HEADER] is recent so captured ones can't be replayed
+pub fn sign(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256={}", hex)
+}
+
+/// Error of a failed attempt, without the URL as it may embed a token
+fn describe(error: reqwest::Error) -> String {
+    let error = error.without_url();
+    match std::error::Error::source(&error) {
+        Some(source) => format!("{}: {}", error, source),
+        None => error.to_string(),
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Lists watched addresses
+#[utoipa::path(
+    get,
+    path = "/admin/watchlist",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Watched addresses", body = Vec<Watch>),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+async fn get_watchlist(webhooks: Webhooks) -> std::result::Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&webhooks.watchlist()))
+}
+
+/// Watches an address, replacing any watch of it
+#[utoipa::path(
+    post,
+    path = "/admin/watchlist",
+    tag = "admin",
+    request_body = Watch,
+    responses(
+        (status = 201, description = "Watched address", body = Watch),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Not an admin key, or read-only storage", body = ErrorBody),
+        (status = 409, description = "Address is listed in the config file", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+async fn put_watch<S: Storage>(
+    watch: Watch,
+    webhooks: Webhooks,
+    mut storage_interface: S,
+) -> std::result::Result<impl Reply, Rejection> {
+    // a stored watch of a listed address could never be deleted, see remove_watch
+    if webhooks.is_listed(&watch.address) {
+        return Err(warp::reject::custom(ApiError::Conflict(format!(
+            "{} is listed in the config file, change it there",
+            watch.address
+        ))));
+    }
+    if let Err(error) = storage_interface.put_watch(&watch).await {
+        return Err(warp::reject::custom(ApiError::from(error)));
+    }
+    webhooks.watch(watch.clone());
+    tracing::info!("Watching {}", watch.address);
+    Ok(warp::reply::with_status(
+        warp::reply::json(&watch),
+        StatusCode::CREATED,
+    ))
+}
+
+/// Stops watching an address added through the API
+#[utoipa::path(
+    delete,
+    path = "/admin/watchlist/{address}",
+    tag = "admin",
+    params(("address" = Address, Path, description = "Watched address")),
+    responses(
+        (status = 204, description = "Address no longer watched"),
+        (status = 400, description = "Invalid address", body = ErrorBody),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Not an admin key, or read-only storage", body = ErrorBody),
+        (status = 404, description = "Address isn't watched", body = ErrorBody),
+        (status = 409, description = "Address is listed in the config file", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+async fn remove_watch<S: Storage>(
+    address: String,
+    webhooks: Webhooks,
+    mut storage_interface: S,
+) -> std::result::Result<impl Reply, Rejection> {
+    let address: Address = address
+        .parse()
+        .map_err(|error: Error| warp::reject::custom(ApiError::from(error)))?;
+    // it would be watched again once the config file is loaded
+    if webhooks.is_listed(&address) {
+        return Err(warp::reject::custom(ApiError::Conflict(format!(
+            "{} is listed in the config file, remove it there",
+            address
+        ))));
+    }
+    if let Err(error) = storage_interface.remove_watch(&address).await {
+        return Err(warp::reject::custom(ApiError::from(error)));
+    }
+    webhooks.unwatch(&address);
+    tracing::info!("Stopped watching {}", address);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Latest webhook deliveries, newest first
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Delivery log", body = Vec<Delivery>),
+        (status = 401, description = "Missing or invalid API key", body = ErrorBody),
+        (status = 403, description = "Not an admin key", body = ErrorBody),
+    ),
+    security(("api_key" = []))
+)]
+async fn get_deliveries(webhooks: Webhooks) -> std::result::Result<impl Reply, Rejection> {
+    Ok(warp::reply::json(&webhooks.deliveries()))
+}
+
+/// Watchlist and delivery log routes, all of them require an admin key
+/// Changing the watchlist also requires writable storage, see [writable]
+/// - GET /admin/watchlist - lists watched addresses
+/// - POST /admin/watchlist - watches an address, see [Watch]
+/// - DELETE /admin/watchlist/<address> - stops watching an address
+/// - GET /admin/webhooks - returns the delivery log, see [Delivery]
+pub fn routes<S>(
+    db: S,
+    keys: ApiKeys,
+    webhooks: Webhooks,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    let webhooks_move = webhooks.clone();
+    let get_watchlist_route = warp::path!("admin" / "watchlist")
+        .and(warp::get())
+        .and(authorize_admin(keys.clone()))
+        .and(warp::any().map(move || webhooks_move.clone()))
+        .and_then(get_watchlist);
+
+    let db_move = db.clone();
+    let webhooks_move = webhooks.clone();
+    let put_watch_route = warp::path!("admin" / "watchlist")
+        .and(warp::post())
+        .and(authorize_admin(keys.clone()))
+        .and(writable(&db))
+        .and(warp::body::json())
+        .and(warp::any().map(move || webhooks_move.clone()))
+        .and(warp::any().map(move || db_move.clone()))
+        .and_then(put_watch);
+
+    let webhooks_move = webhooks.clone();
+    let remove_watch_route = warp::path!("admin" / "watchlist" / String)
+        .and(warp::delete())
+        .and(authorize_admin(keys.clone()))
+        .and(writable(&db))
+        .and(warp::any().map(move || webhooks_move.clone()))
+        .and(warp::any().map(move || db.clone()))
+        .and_then(remove_watch);
+
+    let get_deliveries_route = warp::path!("admin" / "webhooks")
+        .and(warp::get())
+        .and(authorize_admin(keys))
+        .and(warp::any().map(move || webhooks.clone()))
+        .and_then(get_deliveries);
+
+    get_watchlist_route
+        .or(put_watch_route)
+        .or(remove_watch_route)
+        .or(get_deliveries_route)
+}
+
+#[cfg(test)]
+mod webhooks_tests {
+    use crate::aggregator::{Aggregator, QueuedBlocks};
+    use crate::api;
+    use crate::auth::API_KEY_HEADER;
+    use crate::feed;
+    use crate::metrics::Metrics;
+    use crate::storage::InMemoryStorage;
+    use crate::types::ApiKey;
+    use crate::webhooks::*;
+    use solana_program::pubkey::Pubkey;
+    use std::net::SocketAddr;
+    use tokio_util::sync::CancellationToken;
+    use warp::hyper::body::Bytes;
+
+    const SECRET: &str = "webhook-secret";
+
+    /// Request received by the stand-in endpoint
+    struct Received {
+        signature: Option<String>,
+        timestamp: Option<i64>,
+        delivery: Option<String>,
+        body: Bytes,
+    }
+
+    /// Local endpoint answering with `statuses` in turn then 200, recording the requests it receives
+    async fn endpoint(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<Received>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let received_move = received.clone();
+        let statuses = Arc::new(Mutex::new(VecDeque::from(statuses)));
+        let route = warp::post()
+            .and(warp::header::optional::<String>(SIGNATURE_HEADER))
+            .and(warp::header::optional::<i64>(TIMESTAMP_HEADER))
+            .and(warp::header::optional::<String>(DELIVERY_HEADER))
+            .and(warp::body::bytes())
+            .map(move |signature, timestamp, delivery, body| {
+                received_move.lock().unwrap().push(Received {
+                    signature,
+                    timestamp,
+                    delivery,
+                    body,
+                });
+                let status = statuses.lock().unwrap().pop_front().unwrap_or(200);
+                warp::reply::with_status(warp::reply(), StatusCode::from_u16(status).unwrap())
+            });
+        let (address, server) =
+            warp::serve(route).bind_ephemeral(SocketAddr::from(([127, 0, 0, 1], 0)));
+        tokio::spawn(server);
+        (format!("http://{}/hooks", address), received)
+    }
+
+    fn policy() -> DeliveryPolicy {
+        DeliveryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(50),
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    /// Waits for the latest delivery to be delivered or given up on
+    async fn settled(webhooks: &Webhooks) -> Delivery {
+        for _ in 0..500 {
+            match webhooks.deliveries().first() {
+                Some(delivery) if delivery.state != DeliveryState::Pending => {
+                    return delivery.clone()
+                }
+                _ => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+        panic!("webhook neither delivered nor given up on");
+    }
+
+    fn block(height: u64, transactions: Vec<Transaction>) -> Block {
+        Block {
+            height,
+            slot: height + 100,
+            hash: format!("block_hash{}", height),
+            timestamp: height as i64,
+            transactions,
+        }
+    }
+
+    fn transfer(source: Address, destination: Address, amount: u64) -> Transaction {
+        Transaction {
+            source,
+            destination,
+            amount,
+        }
+    }
+
+    /// Stored blocks with transfers of watched addresses are POSTed, signed and retried
+    #[tokio::test]
+    async fn deliver() {
+        let treasury: Address = Pubkey::new_unique().into();
+        let other: Address = Pubkey::new_unique().into();
+        let (url, received) = endpoint(vec![500, 503]).await;
+        let watch = Watch {
+            address: treasury,
+            min_amount: Some(10),
+        };
+        let webhooks = Webhooks::new(&url, SECRET, policy(), [watch]).unwrap();
+
+        let matching = transfer(treasury, other, 20);
+        let blocks = VecDeque::from([
+            block(
+                7,
+                vec![
+                    transfer(treasury, other, 5),
+                    matching.clone(),
+                    transfer(other, other, 100),
+                ],
+            ),
+            // below the threshold
+            block(8, vec![transfer(other, treasury, 1)]),
+        ]);
+        let token = CancellationToken::new();
+        token.cancel();
+        let mut aggregator = Aggregator::new(
+            QueuedBlocks(blocks),
+            token,
+            InMemoryStorage::new(),
+            feed::block_feed(),
+            Metrics::new(),
+            webhooks.clone(),
+        );
+        assert!(matches!(aggregator.run().await, Err(Error::Termination)));
+
+        let delivery = settled(&webhooks).await;
+        assert_eq!(delivery.state, DeliveryState::Delivered);
+        assert_eq!(delivery.id, "block_hash7");
+        assert_eq!(delivery.attempts, 3);
+        assert_eq!(delivery.status, Some(200));
+        assert_eq!(webhooks.deliveries().len(), 1);
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        for request in received.iter() {
+            let timestamp = request.timestamp.unwrap();
+            assert!((unix_now() - timestamp).abs() <= 5);
+            assert_eq!(
+                request.signature,
+                Some(sign(SECRET, timestamp, &request.body))
+            );
+            assert_ne!(
+                request.signature,
+                Some(sign(SECRET, timestamp + 1, &request.body))
+            );
+            assert_eq!(request.delivery.as_deref(), Some("block_hash7"));
+        }
+        let event: WebhookEvent = serde_json::from_slice(&received[0].body).unwrap();
+        assert_eq!(event.height, 7);
+        assert_eq!(event.slot, 107);
+        assert_eq!(event.transactions, vec![matching]);
+    }
+
+    #[tokio::test]
+    async fn give_up() {
+        let treasury: Address = Pubkey::new_unique().into();
+        let watch = Watch {
+            address: treasury,
+            min_amount: None,
+        };
+
+        // refused webhooks aren't retried
+        let (url, received) = endpoint(vec![400]).await;
+        let webhooks = Webhooks::new(&url, SECRET, policy(), [watch.clone()]).unwrap();
+        webhooks.notify(&block(1, vec![transfer(treasury, treasury, 1)]));
+        let delivery = settled(&webhooks).await;
+        assert_eq!(delivery.state, DeliveryState::Failed);
+        assert_eq!(delivery.attempts, 1);
+        assert_eq!(delivery.status, Some(400));
+        assert_eq!(received.lock().unwrap().len(), 1);
+
+        // unreachable endpoints are, up to the policy's attempts
+        let webhooks =
+            Webhooks::new("http://127.0.0.1:1/hooks", SECRET, policy(), [watch]).unwrap();
+        webhooks.notify(&block(1, vec![transfer(treasury, treasury, 1)]));
+        let delivery = settled(&webhooks).await;
+        assert_eq!(delivery.state, DeliveryState::Failed);
+        assert_eq!(delivery.attempts, 3);
+        assert_eq!(delivery.status, None);
+        assert!(delivery.error.is_some());
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = policy();
+        assert_eq!(policy.backoff(1), Duration::from_millis(10));
+        assert_eq!(policy.backoff(2), Duration::from_millis(20));
+        assert_eq!(policy.backoff(10), Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn watchlist() {
+        let storage = InMemoryStorage::new();
+        let listed = Watch {
+            address: Pubkey::new_unique().into(),
+            min_amount: None,
+        };
+        let config = WebhookConfig {
+            watchlist: vec![listed.clone()],
+            ..WebhookConfig::default()
+        };
+        let webhooks = Webhooks::load(&config, &storage).await.unwrap();
+        assert!(!webhooks.is_enabled());
+        let key = |key: &str, admin: bool| ApiKey {
+            key: String::from(key),
+            name: String::from(key),
+            quota: None,
+            admin,
+        };
+        let routes = api::TestRoutes::with_storage(storage.clone())
+            .keys(ApiKeys::new([key("admin", true), key("user", false)]))
+            .webhooks(webhooks.clone())
+            .build();
+
+        let watch = Watch {
+            address: Pubkey::new_unique().into(),
+            min_amount: Some(1000),
+        };
+        let add = |key: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/admin/watchlist")
+                .header(API_KEY_HEADER, key)
+                .json(&watch)
+        };
+        let response = add("user").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = add("admin").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(storage.get_watches().await.unwrap(), vec![watch.clone()]);
+
+        let response = warp::test::request()
+            .path("/admin/watchlist")
+            .header(API_KEY_HEADER, "admin")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let watchlist: Vec<Watch> = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(watchlist.len(), 2);
+        assert!(watchlist.contains(&listed) && watchlist.contains(&watch));
+
+        let stored = block(1, vec![transfer(watch.address, listed.address, 1000)]);
+        assert_eq!(webhooks.event(&stored).unwrap().transactions.len(), 1);
+
+        let remove = |address: &str| {
+            warp::test::request()
+                .method("DELETE")
+                .path(&format!("/admin/watchlist/{}", address))
+                .header(API_KEY_HEADER, "admin")
+        };
+        let response = remove(&watch.address.to_string()).reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(storage.get_watches().await.unwrap().is_empty());
+        let response = remove(&watch.address.to_string()).reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        // watches from the config file can only be changed or removed there
+        let response = warp::test::request()
+            .method("POST")
+            .path("/admin/watchlist")
+            .header(API_KEY_HEADER, "admin")
+            .json(&Watch {
+                min_amount: Some(1),
+                ..listed.clone()
+            })
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let response = remove(&listed.address.to_string()).reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let response = remove("not-an-address").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(webhooks.watchlist(), vec![listed.clone()]);
+
+        let response = warp::test::request()
+            .path("/admin/webhooks")
+            .header(API_KEY_HEADER, "admin")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body().as_ref(), b"[]");
+
+        // replicas replace their watchlist with the listed and stored watches
+        let mut storage_move = storage.clone();
+        storage_move.put_watch(&watch).await.unwrap();
+        webhooks.reload(&config, &storage).await.unwrap();
+        assert_eq!(webhooks.watchlist().len(), 2);
+    }
+}